@@ -2,12 +2,18 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
-use keeper_primitives::{config::Error as ConfigError, ChannelFiles};
+use keeper_primitives::{
+	config::Error as ConfigError, keystore::PasswordSource, Address, ChannelFiles,
+};
 
 const EVENT_TO_IPFS_CHANNEL: &str = "event2ipfs";
 const VERIFY_TO_ATTEST_CHANNEL: &str = "verify2attest";
 const ATTEST_TO_SUBMIT_CHANNEL: &str = "attest2submit";
 const RESUBMIT_CHANNEL: &str = "resubmit";
+const NONCES_DIR: &str = "nonces";
+const RETRY_QUEUE_FILE: &str = "retry_queue.json";
+const REQUESTS_FILE: &str = "requests.json";
+const AUDIT_LOG_FILE: &str = "audit.jsonl";
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "zcloak Keeper", about = "zCloak keeper node start config")]
@@ -17,6 +23,85 @@ pub enum Opt {
 		#[structopt(flatten)]
 		options: StartOptions,
 	},
+	///cancel a pending tx of the keeper with a self transfer at its nonce
+	CancelTx {
+		#[structopt(flatten)]
+		options: StartOptions,
+
+		/// The nonce to cancel
+		#[structopt(long)]
+		nonce: u64,
+
+		/// Cancel the nonce of the resubmit key instead of the submit key
+		#[structopt(long)]
+		resubmit_key: bool,
+
+		/// Cancel the nonce of this key, the submit key, a pool key or the resubmit key
+		#[structopt(long, conflicts_with = "resubmit-key")]
+		key: Option<Address>,
+
+		/// The max fee per gas (gas price of a legacy tx) in wei of the cancel, the fees of the
+		/// pending tx are bumped from the retry queue if not given
+		#[structopt(long)]
+		max_fee: Option<u64>,
+	},
+	///manage the keystores of signing keys
+	Key {
+		#[structopt(subcommand)]
+		cmd: KeyCommand,
+	},
+}
+
+#[derive(Debug, StructOpt)]
+pub enum KeyCommand {
+	/// Create a new key in a keystore
+	New {
+		/// The directory to put the keystore in
+		#[structopt(long, parse(from_os_str))]
+		dir: PathBuf,
+
+		#[structopt(flatten)]
+		password: PasswordOptions,
+	},
+	/// Import a hex private key into a keystore, it is prompted for if no file is given
+	Import {
+		/// The directory to put the keystore in
+		#[structopt(long, parse(from_os_str))]
+		dir: PathBuf,
+
+		/// The file holding the hex private key
+		#[structopt(long, parse(from_os_str))]
+		key_file: Option<PathBuf>,
+
+		#[structopt(flatten)]
+		password: PasswordOptions,
+	},
+	/// Show the address of a keystore
+	Inspect {
+		/// The keystore file
+		#[structopt(parse(from_os_str))]
+		path: PathBuf,
+
+		#[structopt(flatten)]
+		password: PasswordOptions,
+	},
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct PasswordOptions {
+	/// Read the keystore password from this file, it is prompted for otherwise
+	#[structopt(long, parse(from_os_str))]
+	pub password_file: Option<PathBuf>,
+
+	/// Read the keystore password from this environment variable
+	#[structopt(long)]
+	pub password_env: Option<String>,
+}
+
+impl From<PasswordOptions> for PasswordSource {
+	fn from(options: PasswordOptions) -> Self {
+		PasswordSource { password_file: options.password_file, password_env: options.password_env }
+	}
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -36,6 +121,14 @@ pub struct StartOptions {
 	/// The starting block number of scanning node events.
 	#[structopt(short, long)]
 	pub start_number: Option<u64>,
+
+	/// Take plaintext private keys from the config file
+	#[structopt(long)]
+	pub allow_plaintext_key: bool,
+
+	/// Trust the grandpa authority set of the kilt node if no finality checkpoint is configured
+	#[structopt(long)]
+	pub insecure_trust_kilt_node: bool,
 }
 
 impl StartOptions {
@@ -46,7 +139,20 @@ impl StartOptions {
 				let verify_to_attest = dir.join(VERIFY_TO_ATTEST_CHANNEL);
 				let attest_to_submit = dir.join(ATTEST_TO_SUBMIT_CHANNEL);
 				let resubmit = dir.join(RESUBMIT_CHANNEL);
-				Ok(ChannelFiles { event_to_ipfs, verify_to_attest, attest_to_submit, resubmit })
+				let nonces = dir.join(NONCES_DIR);
+				let retry_queue = dir.join(RETRY_QUEUE_FILE);
+				let requests = dir.join(REQUESTS_FILE);
+				let audit_log = dir.join(AUDIT_LOG_FILE);
+				Ok(ChannelFiles {
+					event_to_ipfs,
+					verify_to_attest,
+					attest_to_submit,
+					resubmit,
+					nonces,
+					retry_queue,
+					requests,
+					audit_log,
+				})
 			},
 			None => Err(ConfigError::OtherError("Fail to create channel files.".to_owned())),
 		}
@@ -1,5 +1,5 @@
 use log::info;
-use std::{str::FromStr, sync::Arc};
+use std::sync::Arc;
 
 use tokio::sync::RwLock;
 use yaque::{channel, recovery};
@@ -7,20 +7,24 @@ use yaque::{channel, recovery};
 use keeper_primitives::{
 	config::Error as ConfigError,
 	ipfs::{Error as IpfsError, IPFS_LOG_TARGET},
-	kilt::{Error as KiltError, KILT_LOG_TARGET},
+	kilt::{KILT_HEALTH_CHECK_INTERVAL, KILT_LOG_TARGET},
 	monitor,
 	monitor::MonitorMetrics,
 	moonbeam::{
-		Error as MoonbeamError, MOONBEAM_RESUBMIT_LOG_TARGET, MOONBEAM_SCAN_LOG_TARGET,
-		MOONBEAM_SUBMIT_LOG_TARGET,
+		Error as MoonbeamError, MOONBEAM_AGGREGATOR_LOG_TARGET, MOONBEAM_RESUBMIT_LOG_TARGET,
+		MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SUBMIT_LOG_TARGET,
 	},
-	Config, ConfigInstance, Error, IpfsClient, KiltClient, MoonbeamClient, U64,
+	registry::{self, SharedContract, REGISTRY_LOG_TARGET},
+	signer::{self, Signer, SignerRef},
+	Address, Config, ConfigInstance, Error, IpfsClient, KiltClient, MoonbeamClient, U64,
 };
 use moonbeam::FatTx;
 
 use crate::command::StartOptions;
 
 const SLEEP_SECS: u64 = 1;
+// the longest wait between two attempts to start a task
+const MAX_BACKOFF_SECS: u64 = 60;
 
 // TODO move
 async fn sleep() {
@@ -31,37 +35,109 @@ async fn sleep() {
 pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error> {
 	// load config
 	let start: U64 = start_options.start_number.unwrap_or_default().into();
+	let config_instance = config_instance(start_options).await?;
+
+	// run a keeper
+	run(start, Arc::new(RwLock::new(config_instance))).await?;
+
+	Ok(())
+}
+
+// cancel the tx at `nonce` of the submit key, of the resubmit key, or of the given `key`
+pub async fn cancel_tx(
+	start_options: StartOptions,
+	nonce: u64,
+	resubmit_key: bool,
+	key: Option<Address>,
+	max_fee: Option<u64>,
+) -> std::result::Result<(), Error> {
+	let config = config_instance(start_options).await?;
+	let signer = if let Some(key) = key {
+		std::iter::once(&config.signer)
+			.chain(&config.signer_pool)
+			.chain(&config.signer_optional)
+			.find(|s| s.address() == key)
+			.cloned()
+			.ok_or_else(|| {
+				Error::ConfigLoadError(ConfigError::OtherError(format!(
+					"The key {:?} is not configured",
+					key
+				)))
+			})?
+	} else if resubmit_key {
+		config.signer_optional.clone().ok_or_else(|| {
+			Error::ConfigLoadError(ConfigError::OtherError(
+				"The resubmit key is not configured".to_owned(),
+			))
+		})?
+	} else {
+		config.signer.clone()
+	};
+
+	let hash = moonbeam::cancel_tx(&config, signer.as_ref(), nonce.into(), max_fee.map(Into::into))
+		.await?;
+	log::info!(
+		target: MOONBEAM_RESUBMIT_LOG_TARGET,
+		"[cancel] tx at nonce {:} cancelled by {:?}",
+		nonce,
+		hash
+	);
+	Ok(())
+}
+
+async fn config_instance(
+	start_options: StartOptions,
+) -> std::result::Result<ConfigInstance, Error> {
 	// todo: give it a random name
 	let keeper_name = start_options.clone().name.unwrap_or_default();
 	log::info!("Starting Keeper[{}]", &keeper_name);
 
 	let channel_files = start_options.channel_files()?;
+	let allow_plaintext_key = start_options.allow_plaintext_key;
+	let insecure_trust_kilt_node = start_options.insecure_trust_kilt_node;
 	let config_path = start_options.config.ok_or_else(|| {
 		Error::ConfigLoadError(ConfigError::OtherError(
 			"Config File need to be specific".to_owned(),
 		))
 	})?;
-	let config = Config::load_from_json(&config_path)?;
+	let mut config = Config::load_from_json(&config_path)?;
+	config.kilt.insecure_trust_node = insecure_trust_kilt_node;
 
 	log::info!("[Config] load successfully!");
 	// init config，
-	let moonbeam_client = MoonbeamClient::new(config.moonbeam.url)?;
+	let moonbeam_client = MoonbeamClient::new(config.moonbeam.url.clone())?;
 	let ipfs_client = IpfsClient::new(&config.ipfs.base_url)?;
-	let kilt_client = KiltClient::try_from_url(&config.kilt.url).await?;
+	let kilt_urls: Vec<String> = std::iter::once(config.kilt.url.clone())
+		.chain(config.kilt.backup_urls.clone())
+		.collect();
+	let kilt_client = KiltClient::try_from_urls(&kilt_urls).await?;
 
 	let proof_contract = moonbeam_client.proof_contract(&config.moonbeam.read_contract)?;
+	// the configured aggregator overrides the one in the registry
+	let resolved = match registry::resolve(&moonbeam_client, &proof_contract).await {
+		Ok(resolved) => {
+			log::info!(target: REGISTRY_LOG_TARGET, "[Registry] resolved {:?}", resolved);
+			Some(resolved)
+		},
+		Err(e) => {
+			log::warn!(
+				target: REGISTRY_LOG_TARGET,
+				"fail to look the contracts up in the registry: {:?}",
+				e
+			);
+			None
+		},
+	};
+	let aggregator =
+		registry::pick_aggregator(config.moonbeam.write_contract.as_deref(), resolved.as_ref())?;
+	log::info!(target: REGISTRY_LOG_TARGET, "submit to the aggregator at {}", aggregator);
 	let aggregator_contract =
-		moonbeam_client.aggregator_contract(&config.moonbeam.write_contract)?;
+		SharedContract::new(moonbeam_client.aggregator_contract(&aggregator)?);
 
-	let moonbeam_worker_pri = secp256k1::SecretKey::from_str(&config.moonbeam.private_key)?;
-	let moonbeam_worker_pri_optional = if config.moonbeam.private_key_optional.is_some() {
-		Some(
-			secp256k1::SecretKey::from_str(&config.moonbeam.private_key_optional.unwrap())
-				.expect("Wrong optional secret key"),
-		)
-	} else {
-		None
-	};
+	let (signer, signer_optional) =
+		signer::signers(&config.moonbeam, &moonbeam_client, allow_plaintext_key)?;
+	let signer_pool =
+		signer::pool_signers(&config.moonbeam, &moonbeam_client, allow_plaintext_key)?;
 
 	#[cfg(feature = "monitor")]
 	let bot_url = config.monitor.bot_url;
@@ -72,20 +148,20 @@ pub async fn start(start_options: StartOptions) -> std::result::Result<(), Error
 		moonbeam_client,
 		ipfs_client,
 		kilt_client,
+		kilt_config: config.kilt,
+		moonbeam_config: config.moonbeam,
 		proof_contract,
 		aggregator_contract,
-		private_key: moonbeam_worker_pri,
-		private_key_optional: moonbeam_worker_pri_optional,
+		signer,
+		signer_optional,
+		signer_pool,
 		#[cfg(feature = "monitor")]
 		bot_url,
 	};
 
 	log::info!("ConfigInstance initialized");
 
-	// run a keeper
-	run(start, Arc::new(RwLock::new(config_instance))).await?;
-
-	Ok(())
+	Ok(config_instance)
 }
 
 // handle detailed process
@@ -120,6 +196,38 @@ pub async fn run(
 	let (mut re_submit_sender, mut re_submit_receiver) =
 		channel(&config_channels.resubmit).unwrap();
 
+	// nonces of the signing keys, shared by submit and resubmit
+	let nonce_managers = {
+		let config = config.read().await;
+		let signers: Vec<SignerRef> = std::iter::once(config.signer.clone())
+			.chain(config.signer_pool.clone())
+			.chain(config.signer_optional.clone())
+			.collect();
+		Arc::new(
+			moonbeam::NonceManagers::load(&config, &signers, &config.channel_files.nonces)
+				.await
+				.map_err(|e| {
+					log::error!(
+						target: MOONBEAM_SUBMIT_LOG_TARGET,
+						"fail to load the nonce managers: {:?}",
+						e
+					);
+					e
+				})?,
+		)
+	};
+	let nonce_managers1 = nonce_managers.clone();
+
+	// balance levels of the signing keys, kept by the balance task
+	let balances = Arc::new(moonbeam::Balances::default());
+	let balances1 = balances.clone();
+	let balances2 = balances.clone();
+
+	// what the aggregator emitted about each request, kept by the aggregator scan
+	let tracker = Arc::new(moonbeam::RequestTracker::load(&config_channels.requests)?);
+	let tracker1 = tracker.clone();
+	let tracker2 = tracker.clone();
+
 	// alert message sending
 	let (monitor_sender, mut monitor_receiver) =
 		tokio::sync::mpsc::channel::<monitor::MonitorMetrics>(100);
@@ -130,6 +238,9 @@ pub async fn run(
 	let config3 = configs.clone();
 	let config4 = configs.clone();
 	let config5 = configs.clone();
+	let config6 = configs.clone();
+	let config7 = configs.clone();
+	let config8 = configs.clone();
 
 	// spread monitors
 	let monitor_sender1 = monitor_sender.clone();
@@ -137,6 +248,9 @@ pub async fn run(
 	let monitor_sender3 = monitor_sender.clone();
 	let monitor_sender4 = monitor_sender.clone();
 	let monitor_sender5 = monitor_sender.clone();
+	let monitor_sender6 = monitor_sender.clone();
+	let monitor_sender7 = monitor_sender.clone();
+	let monitor_sender8 = monitor_sender.clone();
 
 	// 1. scan moonbeam proof event, and push them to event channel
 	let task_scan = tokio::spawn(async move {
@@ -223,9 +337,41 @@ pub async fn run(
 	// 3. query kilt
 	let task_kilt_attest = tokio::spawn(async move {
 		let config = config3.read().await;
+		let mut backoff = SLEEP_SECS;
+		let mut failed_over = false;
+		let mut state = loop {
+			match kilt::KiltState::new(&config.kilt_client, &config.kilt_config).await {
+				Ok(s) => break s,
+				Err(e) if e.is_retryable() => {
+					log::error!(
+						target: KILT_LOG_TARGET,
+						"fail to init the kilt state, retry in {:} secs: {:?}",
+						backoff,
+						e
+					);
+					tokio::time::sleep(std::time::Duration::from_secs(backoff)).await;
+					backoff = (backoff * 2).min(MAX_BACKOFF_SECS);
+				},
+				// ask the next node once if the answer of this one does not hold up
+				Err(e) if e.is_untrusted() && !failed_over && config.kilt_client.fail_over() => {
+					log::error!(
+						target: KILT_LOG_TARGET,
+						"fail to init the kilt state, try the next endpoint: {:?}",
+						e
+					);
+					failed_over = true;
+				},
+				Err(e) => return (None, e.into()),
+			}
+		};
+		state.monitor = Some(monitor_sender3.clone());
 		loop {
-			let res =
-				kilt::task_attestation(&config, (&mut submit_sender, &mut attest_receiver)).await;
+			let res = kilt::task_attestation(
+				&config,
+				(&mut submit_sender, &mut attest_receiver),
+				&mut state,
+			)
+			.await;
 
 			if let Err(e) = res {
 				log::error!(
@@ -243,7 +389,7 @@ pub async fn run(
 				}
 
 				match e.1 {
-					Error::KiltError(KiltError::KiltClientError(_e)) => {
+					Error::KiltError(ref kilt_error) if kilt_error.is_retryable() => {
 						// TODO need retry
 						sleep().await;
 						continue
@@ -258,12 +404,19 @@ pub async fn run(
 	let task_submit_txs = tokio::spawn(async move {
 		let config = config4.read().await;
 		let mut last_sent_tx = FatTx::default();
+		// spreads the submissions over the submit keys
+		let scheduler = moonbeam::Scheduler::new(&config);
 
 		loop {
 			let res = moonbeam::task_submit(
 				&config,
 				(&mut re_submit_sender, &mut submit_receiver),
 				&mut last_sent_tx,
+				&monitor_sender4,
+				&scheduler,
+				&nonce_managers,
+				&balances,
+				&tracker2,
 			)
 			.await;
 			if let Err(e) = res {
@@ -299,7 +452,19 @@ pub async fn run(
 	// task 5: resubmit
 	let task_resubmit_txs = tokio::spawn(async move {
 		let config = config5.read().await;
-		let queue = moonbeam::create_retry_queue();
+		let queue = loop {
+			match moonbeam::load_retry_queue(&config, &config.aggregator_contract.get()).await {
+				Ok(q) => break q,
+				Err(e) => {
+					log::error!(
+						target: MOONBEAM_RESUBMIT_LOG_TARGET,
+						"fail to load the retry queue, {:?}",
+						e
+					);
+					sleep().await;
+				},
+			}
+		};
 		let mut last_sent_at = U64::default();
 
 		loop {
@@ -310,6 +475,10 @@ pub async fn run(
 				&mut re_submit_receiver,
 				queue.clone(),
 				&mut last_sent_at,
+				&monitor_sender5,
+				&nonce_managers1,
+				&balances1,
+				&tracker,
 			)
 			.await;
 			if let Err(e) = res {
@@ -342,6 +511,60 @@ pub async fn run(
 		}
 	});
 
+	// task 6: check the balances of the signing keys
+	let task_balance = tokio::spawn(async move {
+		let config = config6.read().await;
+		moonbeam::task_balance(&config, &balances2, &monitor_sender6).await
+	});
+
+	// task 7: follow the votes and the final verdicts on the aggregator
+	let task_aggregator_scan = tokio::spawn(async move {
+		let config = config7.read().await;
+		loop {
+			let res =
+				moonbeam::task_aggregator_scan(&config, &tracker1, start, &monitor_sender7).await;
+			if let Err(e) = res {
+				log::error!(
+					target: MOONBEAM_AGGREGATOR_LOG_TARGET,
+					"[outer error] task aggregator scan error, {:?}",
+					e
+				);
+				if cfg!(feature = "monitor") {
+					let monitor_metrics = MonitorMetrics::new_with_target_and_error(
+						MOONBEAM_AGGREGATOR_LOG_TARGET.to_string(),
+						&e,
+						config.name.clone(),
+					);
+					let _res = monitor_sender7.send(monitor_metrics).await;
+				}
+
+				match e.1 {
+					Error::MoonbeamError(MoonbeamError::Web3Error(_)) |
+					Error::MoonbeamError(MoonbeamError::Web3ContractError(_)) => {
+						sleep().await;
+						continue
+					},
+					_ => return e,
+				};
+			}
+		}
+	});
+
+	// task 8: follow the contracts in the registry
+	let task_registry = tokio::spawn(async move {
+		let config = config8.read().await;
+		moonbeam::task_registry(&config, &monitor_sender8).await
+	});
+
+	// keep the kilt client on a healthy endpoint
+	let kilt_client = configs.read().await.kilt_client.clone();
+	let task_kilt_health = tokio::spawn(async move {
+		loop {
+			tokio::time::sleep(std::time::Duration::from_secs(KILT_HEALTH_CHECK_INTERVAL)).await;
+			kilt_client.health_check().await;
+		}
+	});
+
 	// monitor
 	let task_monitor_handle = tokio::spawn(async move {
 		while let Some(_msg) = monitor_receiver.recv().await {
@@ -363,6 +586,10 @@ pub async fn run(
 		task_kilt_attest,
 		task_submit_txs,
 		task_resubmit_txs,
+		task_balance,
+		task_aggregator_scan,
+		task_registry,
+		task_kilt_health,
 		task_monitor_handle
 	)?;
 	Ok(())
@@ -0,0 +1,42 @@
+use std::str::FromStr;
+
+use keeper_primitives::{
+	keystore::{self, PasswordSource},
+	Error, Key, SecretKeyRef,
+};
+use secp256k1::SecretKey;
+
+use crate::command::KeyCommand;
+
+pub fn run(cmd: KeyCommand) -> std::result::Result<(), Error> {
+	match cmd {
+		KeyCommand::New { dir, password } => {
+			let password = PasswordSource::from(password).read_new("Password of the keystore: ")?;
+			let key = keystore::generate();
+			let path = keystore::encrypt(&dir, &key, password)?;
+			print_key(&key, &path);
+		},
+		KeyCommand::Import { dir, key_file, password } => {
+			let hex_key = match key_file {
+				Some(file) => std::fs::read_to_string(file)?,
+				None => rpassword::prompt_password("Hex private key: ")?,
+			};
+			let key = SecretKey::from_str(hex_key.trim().trim_start_matches("0x"))?;
+			let password = PasswordSource::from(password).read_new("Password of the keystore: ")?;
+			let path = keystore::encrypt(&dir, &key, password)?;
+			print_key(&key, &path);
+		},
+		KeyCommand::Inspect { path, password } => {
+			let password = PasswordSource::from(password)
+				.read(&format!("Password of {}: ", path.display()))?;
+			let key = keystore::decrypt(&path, password)?;
+			print_key(&key, &path);
+		},
+	}
+	Ok(())
+}
+
+fn print_key(key: &SecretKey, path: &std::path::Path) {
+	println!("address: {:?}", SecretKeyRef::new(key).address());
+	println!("keystore: {}", path.display());
+}
@@ -7,6 +7,7 @@ use keeper_primitives::Error;
 
 mod command;
 mod entry;
+mod key;
 mod runner;
 mod tasks;
 
@@ -23,6 +24,10 @@ async fn main() -> Result<(), Error> {
 			let f = f.fuse();
 			runner::run_until_exit(f).await?;
 		},
+		Opt::CancelTx { options, nonce, resubmit_key, key, max_fee } => {
+			entry::cancel_tx(options, nonce, resubmit_key, key, max_fee).await?;
+		},
+		Opt::Key { cmd } => key::run(cmd)?,
 	}
 	Ok(())
 }
@@ -0,0 +1,202 @@
+use std::{
+	collections::HashMap,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use jsonrpsee::{
+	types::{to_json_value, traits::SubscriptionClient},
+	ws_client::{WsClient, WsClientBuilder},
+};
+use sp_core::storage::StorageChangeSet;
+use tokio::sync::Mutex;
+
+use keeper_primitives::{
+	kilt::{
+		get_attestation_storage_key, Attestation, AttestationCacheConfig, Error, KILT_LOG_TARGET,
+	},
+	Hash, StorageData, StorageKey,
+};
+
+use crate::AttestationLayout;
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+	attestation: Attestation,
+	// how the attestation is stored by the runtime, to compare notified values
+	layout: AttestationLayout,
+	inserted: Instant,
+	// the finalized block it was checked against a storage proof at, if proofs are required
+	proven_at: Option<Hash>,
+}
+
+type Entries = Arc<Mutex<HashMap<StorageKey, CacheEntry>>>;
+
+/// Cache of valid attestations. Every cached key is watched with `state_subscribeStorage`, an
+/// entry is evicted as soon as its storage changes, e.g. on revocation, or when the subscription
+/// watching it breaks. Entries also expire after `ttl`. The notifications are not proven, so if
+/// storage proofs are required an entry is only served for the block it was proven at.
+pub struct AttestationCache {
+	ttl: Duration,
+	entries: Entries,
+	subscriber: WsClient,
+}
+
+impl AttestationCache {
+	pub async fn new(config: &AttestationCacheConfig) -> Result<Self, Error> {
+		if !(config.ws_url.starts_with("ws://") || config.ws_url.starts_with("wss://")) {
+			return Err(Error::UrlFormatError(
+				"Kilt attestation cache subscription must start with ws or wss".to_owned(),
+			))
+		}
+		let subscriber = WsClientBuilder::default().build(&config.ws_url).await?;
+		Ok(AttestationCache {
+			// a zero interval can not be ticked
+			ttl: Duration::from_secs(config.ttl_secs.max(1)),
+			entries: Default::default(),
+			subscriber,
+		})
+	}
+
+	/// the cached attestation of `root_hash`, if proofs are required it must be proven at
+	/// `proven_at`
+	pub async fn get(&self, root_hash: Hash, proven_at: Option<Hash>) -> Option<Attestation> {
+		let key = get_attestation_storage_key::<Hash>(root_hash);
+		let mut entries = self.entries.lock().await;
+		match entries.get(&key) {
+			// proven at another block, it is proven again and refreshed
+			Some(entry) if proven_at.is_some() && entry.proven_at != proven_at => None,
+			Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.attestation.clone()),
+			Some(_) => {
+				entries.remove(&key);
+				None
+			},
+			None => None,
+		}
+	}
+
+	/// cache unrevoked attestations, they are only inserted once their storage is watched.
+	/// Entries already watched are refreshed in place.
+	pub async fn insert(
+		&self,
+		attestations: Vec<(Hash, Attestation)>,
+		layout: AttestationLayout,
+		proven_at: Option<Hash>,
+	) {
+		let mut attestations: Vec<_> = attestations
+			.into_iter()
+			.filter(|(_, a)| !a.revoked)
+			.map(|(root_hash, a)| (get_attestation_storage_key::<Hash>(root_hash), a))
+			.collect();
+		{
+			let mut entries = self.entries.lock().await;
+			attestations.retain(|(key, attestation)| match entries.get_mut(key) {
+				Some(entry) => {
+					entry.attestation = attestation.clone();
+					entry.layout = layout;
+					entry.proven_at = proven_at;
+					false
+				},
+				None => true,
+			});
+		}
+		if attestations.is_empty() {
+			return
+		}
+
+		let keys: Vec<StorageKey> = attestations.iter().map(|(k, _)| k.clone()).collect();
+		let params = match to_json_value(&keys) {
+			Ok(p) => vec![p],
+			Err(_) => return,
+		};
+		let subscription = self
+			.subscriber
+			.subscribe::<StorageChangeSet<Hash>>(
+				"state_subscribeStorage",
+				Some(params.into()),
+				"state_unsubscribeStorage",
+			)
+			.await;
+		let mut subscription = match subscription {
+			Ok(s) => s,
+			Err(e) => {
+				log::warn!(
+					target: KILT_LOG_TARGET,
+					"fail to watch attestations, they are not cached. err: {:?}",
+					e
+				);
+				return
+			},
+		};
+
+		{
+			let mut entries = self.entries.lock().await;
+			let inserted = Instant::now();
+			for (key, attestation) in attestations {
+				entries.insert(key, CacheEntry { attestation, layout, inserted, proven_at });
+			}
+		}
+
+		let entries = self.entries.clone();
+		let ttl = self.ttl;
+		tokio::spawn(async move {
+			let mut check = tokio::time::interval(ttl);
+			loop {
+				tokio::select! {
+					notification = subscription.next() => match notification {
+						Ok(Some(change_set)) => {
+							let mut entries = entries.lock().await;
+							for (key, value) in change_set.changes {
+								evict_if_changed(&mut entries, &key, value);
+							}
+						},
+						// nothing watches these keys any more
+						_ => {
+							log::warn!(
+								target: KILT_LOG_TARGET,
+								"attestation subscription closed, evict {:} cached keys",
+								keys.len()
+							);
+							let mut entries = entries.lock().await;
+							keys.iter().for_each(|k| {
+								entries.remove(k);
+							});
+							break
+						},
+					},
+					_ = check.tick() => {
+						let entries = entries.lock().await;
+						// all watched keys have expired or been evicted, drop
+						// the subscription
+						if !keys.iter().any(|k| entries.contains_key(k)) {
+							break
+						}
+					},
+				}
+			}
+		});
+	}
+}
+
+// the first notification of a subscription holds the current values, so the entry is only evicted
+// if the value differs from the cached one
+fn evict_if_changed(
+	entries: &mut HashMap<StorageKey, CacheEntry>,
+	key: &StorageKey,
+	value: Option<StorageData>,
+) {
+	let unchanged = match (entries.get(key), value) {
+		(Some(entry), Some(data)) =>
+			entry.layout.decode(&data.0).map(|a| a == entry.attestation).unwrap_or(false),
+		(Some(_), None) => false,
+		(None, _) => return,
+	};
+	if !unchanged {
+		log::info!(
+			target: KILT_LOG_TARGET,
+			"attestation storage 0x{:} changed, evict it from the cache",
+			hex::encode(&key.0)
+		);
+		entries.remove(key);
+	}
+}
@@ -0,0 +1,172 @@
+use std::collections::{BTreeSet, HashMap};
+
+use codec::Decode;
+use sp_runtime::AccountId32 as AccountId;
+
+use keeper_primitives::{
+	kilt::{
+		get_delegation_node_storage_key, Balance, Deposit, Error, KiltClient, RejectReason,
+		KILT_LOG_TARGET,
+	},
+	Hash,
+};
+
+// `Permissions::ATTEST` of the delegation pallet
+pub const PERMISSION_ATTEST: u32 = 0b0000_0001;
+// stop walking a hierarchy after so many parents, a valid one never gets that deep
+const MAX_DELEGATION_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, Decode, PartialEq)]
+pub struct DelegationDetails {
+	pub owner: AccountId,
+	pub revoked: bool,
+	pub permissions: u32,
+}
+
+#[derive(Clone, Debug, Decode, PartialEq)]
+pub struct DelegationNode {
+	pub hierarchy_root_id: Hash,
+	pub parent: Option<Hash>,
+	pub children: BTreeSet<Hash>,
+	pub details: DelegationDetails,
+	pub deposit: Deposit<AccountId, Balance>,
+}
+
+pub type DelegationNodes = HashMap<Hash, Option<DelegationNode>>;
+
+/// fetch the delegation nodes of `leaves` and all of their parents at block `at`,
+/// one request for each level of the hierarchies
+pub async fn query_delegation_nodes(
+	client: &KiltClient,
+	leaves: Vec<Hash>,
+	at: Hash,
+	state_root: Option<Hash>,
+) -> Result<DelegationNodes, Error> {
+	let mut nodes = DelegationNodes::new();
+	let mut next = leaves;
+
+	for _ in 0..MAX_DELEGATION_DEPTH {
+		next.sort();
+		next.dedup();
+		next.retain(|id| !nodes.contains_key(id));
+		if next.is_empty() {
+			break
+		}
+
+		let keys: Vec<_> =
+			next.iter().map(|id| get_delegation_node_storage_key::<Hash>(*id)).collect();
+		let values = super::query_storage(client, &keys, at, state_root).await?;
+
+		let mut parents = vec![];
+		for (id, key) in next.iter().zip(keys.iter()) {
+			let node = match values.get(key).cloned().flatten() {
+				Some(data) => Some(DelegationNode::decode(&mut data.0.as_slice())?),
+				None => None,
+			};
+			if let Some(parent) = node.as_ref().and_then(|n| n.parent) {
+				parents.push(parent);
+			}
+			nodes.insert(*id, node);
+		}
+		next = parents;
+	}
+
+	Ok(nodes)
+}
+
+/// walk from `leaf` up to the hierarchy root, every node must exist and be unrevoked and `leaf`
+/// must be owned by `attester` and allowed to attest. Returns the ids from the leaf to the root.
+pub fn check_delegation(
+	nodes: &DelegationNodes,
+	leaf: Hash,
+	attester: &AccountId,
+) -> Result<Vec<Hash>, RejectReason> {
+	let mut chain = vec![];
+	let mut cursor = Some(leaf);
+
+	while let Some(id) = cursor {
+		if chain.len() >= MAX_DELEGATION_DEPTH || chain.contains(&id) {
+			log::warn!(
+				target: KILT_LOG_TARGET,
+				"delegation hierarchy of {:} is too deep or cyclic",
+				leaf
+			);
+			return Err(RejectReason::DelegationHierarchyInvalid(leaf.0))
+		}
+		let node = nodes
+			.get(&id)
+			.and_then(|n| n.as_ref())
+			.ok_or(RejectReason::DelegationNotFound(id.0))?;
+		if node.details.revoked {
+			return Err(RejectReason::DelegationRevoked(id.0))
+		}
+		if id == leaf && node.details.owner != *attester {
+			return Err(RejectReason::DelegationNotOwned(id.0))
+		}
+		if id == leaf && node.details.permissions & PERMISSION_ATTEST == 0 {
+			return Err(RejectReason::DelegationCannotAttest(id.0))
+		}
+
+		chain.push(id);
+		cursor = node.parent;
+	}
+
+	Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn node(parent: Option<Hash>, revoked: bool, permissions: u32) -> Option<DelegationNode> {
+		let owner = AccountId::new([1u8; 32]);
+		Some(DelegationNode {
+			hierarchy_root_id: Hash::repeat_byte(1),
+			parent,
+			children: BTreeSet::new(),
+			details: DelegationDetails { owner: owner.clone(), revoked, permissions },
+			deposit: Deposit { owner, amount: 0 },
+		})
+	}
+
+	#[test]
+	fn check_delegation_should_work() {
+		let (root, middle, leaf) =
+			(Hash::repeat_byte(1), Hash::repeat_byte(2), Hash::repeat_byte(3));
+		let mut nodes = DelegationNodes::new();
+		nodes.insert(root, node(None, false, 0b11));
+		nodes.insert(middle, node(Some(root), false, 0b10));
+		nodes.insert(leaf, node(Some(middle), false, PERMISSION_ATTEST));
+		let attester = AccountId::new([1u8; 32]);
+
+		assert_eq!(check_delegation(&nodes, leaf, &attester), Ok(vec![leaf, middle, root]));
+		// the middle node can only delegate
+		assert_eq!(
+			check_delegation(&nodes, middle, &attester),
+			Err(RejectReason::DelegationCannotAttest(middle.0))
+		);
+		// delegated to someone else
+		assert_eq!(
+			check_delegation(&nodes, leaf, &AccountId::new([2u8; 32])),
+			Err(RejectReason::DelegationNotOwned(leaf.0))
+		);
+
+		nodes.insert(root, node(Some(leaf), false, 0b11));
+		assert_eq!(
+			check_delegation(&nodes, leaf, &attester),
+			Err(RejectReason::DelegationHierarchyInvalid(leaf.0))
+		);
+
+		nodes.insert(root, node(None, true, 0b11));
+		assert_eq!(
+			check_delegation(&nodes, leaf, &attester),
+			Err(RejectReason::DelegationRevoked(root.0))
+		);
+
+		nodes.insert(middle, None);
+		assert_eq!(
+			check_delegation(&nodes, leaf, &attester),
+			Err(RejectReason::DelegationNotFound(middle.0))
+		);
+	}
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use codec::Decode;
+use sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+use sp_runtime::AccountId32 as AccountId;
+
+use keeper_primitives::{
+	kilt::{
+		get_did_blacklist_storage_key, get_did_storage_key, get_web3_name_storage_key,
+		AttesterIdentity, Error, KiltClient, KILT_SS58_PREFIX,
+	},
+	Hash,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DidStatus {
+	Active(AttesterIdentity),
+	// the did has been deleted and can never be created again
+	Deleted,
+	// the attester has no full did
+	None,
+}
+
+pub type DidStatuses = HashMap<AccountId, DidStatus>;
+
+/// resolve `attesters` to their full did and web3name at block `at`, with one request
+pub async fn query_dids(
+	client: &KiltClient,
+	mut attesters: Vec<AccountId>,
+	at: Hash,
+	state_root: Option<Hash>,
+) -> Result<DidStatuses, Error> {
+	attesters.sort();
+	attesters.dedup();
+	if attesters.is_empty() {
+		return Ok(DidStatuses::new())
+	}
+
+	let keys: Vec<_> = attesters
+		.iter()
+		.flat_map(|a| {
+			[get_did_storage_key(a), get_did_blacklist_storage_key(a), get_web3_name_storage_key(a)]
+		})
+		.collect();
+	let values = super::query_storage(client, &keys, at, state_root).await?;
+
+	let mut statuses = DidStatuses::new();
+	for (attester, keys) in attesters.into_iter().zip(keys.chunks(3)) {
+		let exists = |key| values.get(key).map(|v| v.is_some()).unwrap_or(false);
+		let status = if exists(&keys[0]) {
+			let web3name = match values.get(&keys[2]).cloned().flatten() {
+				Some(data) => {
+					let name = Vec::<u8>::decode(&mut data.0.as_slice())?;
+					Some(String::from_utf8_lossy(&name).into_owned())
+				},
+				None => None,
+			};
+			DidStatus::Active(AttesterIdentity { did: did_uri(&attester), web3name })
+		} else if exists(&keys[1]) {
+			DidStatus::Deleted
+		} else {
+			DidStatus::None
+		};
+		statuses.insert(attester, status);
+	}
+
+	Ok(statuses)
+}
+
+pub fn did_uri(account: &AccountId) -> String {
+	format!(
+		"did:kilt:{:}",
+		account.to_ss58check_with_version(Ss58AddressFormat::Custom(KILT_SS58_PREFIX))
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn did_uri_should_work() {
+		let account =
+			AccountId::from_ss58check("4pf1fzQjRnNcLxKDn6vE1R3nBEu1MUbW4Wz63uKFQUD7WHR2").unwrap();
+		assert_eq!(did_uri(&account), "did:kilt:4pf1fzQjRnNcLxKDn6vE1R3nBEu1MUbW4Wz63uKFQUD7WHR2");
+	}
+}
@@ -0,0 +1,426 @@
+use std::collections::{HashMap, HashSet};
+
+use codec::Decode;
+use finality_grandpa::{Commit, Message};
+use sp_finality_grandpa::{
+	check_message_signature, AuthorityId, AuthorityList, AuthoritySignature, ConsensusLog, SetId,
+	GRANDPA_ENGINE_ID,
+};
+use sp_runtime::{generic::OpaqueDigestItemId, traits::Header as HeaderT};
+
+use keeper_primitives::{
+	kilt::{
+		get_storage_value_key, BlockNumber, Error, FinalityCheckpoint, Header, KiltClient,
+		GRANDPA_PALLET_PREFIX, GRANDPA_SET_ID_STORAGE_PREFIX, KILT_LOG_TARGET,
+	},
+	Bytes, Hash,
+};
+
+const GRANDPA_AUTHORITIES_CALL: &str = "GrandpaApi_grandpa_authorities";
+
+/// Justification of a finalized block, same layout as `sc_finality_grandpa::GrandpaJustification`
+#[derive(Clone, Debug, Decode)]
+pub struct GrandpaJustification {
+	pub round: u64,
+	pub commit: Commit<Hash, BlockNumber, AuthoritySignature, AuthorityId>,
+	pub votes_ancestries: Vec<Header>,
+}
+
+/// Response of `grandpa_proveFinality`, same layout as `sc_finality_grandpa::FinalityProof`
+#[derive(Clone, Debug, Decode)]
+pub struct FinalityProof {
+	// the hash of block F for which justification is provided
+	pub block: Hash,
+	// justification of the block F
+	pub justification: Vec<u8>,
+	// the set of headers in the range (B; F] that the caller does not know
+	pub unknown_headers: Vec<Header>,
+}
+
+/// Follows the grandpa authority set of kilt, and uses it to check that a header
+/// has really been finalized before trusting its state root. Every header from the first
+/// checked block on is scanned for authority set changes.
+#[derive(Clone, Debug)]
+pub struct FinalityTracker {
+	set_id: SetId,
+	authorities: AuthorityList,
+	// the first block whose header is not scanned yet
+	next: Option<BlockNumber>,
+	// changes scheduled in scanned headers, (enacted at, next authorities)
+	pending: Vec<(BlockNumber, AuthorityList)>,
+}
+
+impl FinalityTracker {
+	pub fn new(set_id: SetId, authorities: AuthorityList) -> Self {
+		FinalityTracker { set_id, authorities, next: None, pending: vec![] }
+	}
+
+	/// build the tracker from the configured checkpoint. Without one the current authority
+	/// set is fetched from the node, which is only done if `insecure_trust_node` is set
+	pub async fn bootstrap(
+		client: &KiltClient,
+		checkpoint: Option<&FinalityCheckpoint>,
+		insecure_trust_node: bool,
+	) -> Result<Self, Error> {
+		if let Some(checkpoint) = checkpoint {
+			let genesis = client
+				.block_hash(Some(0))
+				.await?
+				.ok_or_else(|| Error::UnexpectedResponse("no genesis block".to_owned()))?;
+			if genesis != checkpoint.genesis_hash {
+				return Err(Error::FinalityError(format!(
+					"the node serves chain {:}, the checkpoint is for {:}",
+					genesis, checkpoint.genesis_hash
+				)))
+			}
+			let mut authorities = vec![];
+			for (id, weight) in &checkpoint.authorities {
+				let id = id.strip_prefix("0x").unwrap_or(id);
+				let raw = hex::decode(id).map_err(|e| {
+					Error::InvalidFinalityCheckpoint(format!("invalid authority id: {:}", e))
+				})?;
+				let id = AuthorityId::decode(&mut raw.as_slice()).map_err(|e| {
+					Error::InvalidFinalityCheckpoint(format!("invalid authority id: {:}", e))
+				})?;
+				authorities.push((id, *weight));
+			}
+			return Ok(Self::new(checkpoint.set_id, authorities))
+		}
+		if !insecure_trust_node {
+			return Err(Error::InvalidFinalityCheckpoint(
+				"`finality_checkpoint` is required to verify storage proofs, or start with --insecure-trust-kilt-node".to_owned(),
+			))
+		}
+
+		let head = client.finalized_head().await?;
+		let set_id_key =
+			get_storage_value_key(GRANDPA_PALLET_PREFIX, GRANDPA_SET_ID_STORAGE_PREFIX);
+		let set_id = match client.request_storage(&set_id_key, Some(head)).await? {
+			Some(data) => SetId::decode(&mut data.0.as_slice())?,
+			None => 0,
+		};
+		let raw_authorities =
+			client.state_call(GRANDPA_AUTHORITIES_CALL, Bytes(vec![]), Some(head)).await?;
+		let authorities = AuthorityList::decode(&mut raw_authorities.0.as_slice())?;
+
+		log::warn!(
+			target: KILT_LOG_TARGET,
+			"insecure: no finality checkpoint configured, trust grandpa authority set #{:} with {:} authorities fetched at block {:}",
+			set_id,
+			authorities.len(),
+			head
+		);
+
+		Ok(Self::new(set_id, authorities))
+	}
+
+	/// fetch the latest finalized header from the node and check that it is covered by a valid
+	/// grandpa justification, returns (block hash, state root)
+	pub async fn finalized_state_root(
+		&mut self,
+		client: &KiltClient,
+	) -> Result<(Hash, Hash), Error> {
+		let head = client.finalized_head().await?;
+		let header = fetch_header(client, head).await?;
+		let target = *header.number();
+
+		// the node proves the last block of the set a block is in, so the sets between the
+		// last scanned block and the head are followed one after another
+		let mut from = self.next.unwrap_or(target).min(target);
+		loop {
+			let from_header = if from == target {
+				header.clone()
+			} else {
+				let hash = client.block_hash(Some(from)).await?.ok_or_else(|| {
+					Error::FinalityError(format!("unknown finalized block #{:}", from))
+				})?;
+				fetch_header(client, hash).await?
+			};
+
+			let encoded = client.prove_finality(from).await?.ok_or_else(|| {
+				Error::FinalityError(format!("no finality proof for block #{:}", from))
+			})?;
+			let proof = FinalityProof::decode(&mut encoded.0.as_slice())?;
+			let justification = GrandpaJustification::decode(&mut proof.justification.as_slice())?;
+
+			self.verify_justification(proof.block, &justification)?;
+			check_ancestry(&from_header, proof.block, &proof.unknown_headers)?;
+
+			// the justified block may enact a new authority set which
+			// must be used for the next proof
+			let justified = justification.commit.target_number;
+			self.apply_authority_changes(
+				justified,
+				std::iter::once(&from_header).chain(proof.unknown_headers.iter()),
+			)?;
+			self.next = Some(justified + 1);
+
+			if justified >= target {
+				break
+			}
+			if justified < from {
+				return Err(Error::FinalityError(format!(
+					"finality proof of block #{:} justifies #{:} before it",
+					from, justified
+				)))
+			}
+			from = justified + 1;
+		}
+
+		Ok((head, *header.state_root()))
+	}
+
+	/// check that more than 2/3 of the authorities of the current set have signed
+	/// precommits for `target` or one of its descendants
+	pub fn verify_justification(
+		&self,
+		target: Hash,
+		justification: &GrandpaJustification,
+	) -> Result<(), Error> {
+		let commit = &justification.commit;
+		if commit.target_hash != target {
+			return Err(Error::FinalityError(format!(
+				"justification targets {:}, expected {:}",
+				commit.target_hash, target
+			)))
+		}
+
+		let ancestry: HashMap<Hash, &Header> =
+			justification.votes_ancestries.iter().map(|h| (h.hash(), h)).collect();
+
+		let mut seen = HashSet::new();
+		let mut signed_weight = 0u64;
+		for signed in &commit.precommits {
+			let weight = self
+				.authorities
+				.iter()
+				.find(|(id, _)| id == &signed.id)
+				.map(|(_, weight)| *weight)
+				.ok_or_else(|| {
+					Error::FinalityError(format!(
+						"precommit from unknown authority {:?} in set #{:}",
+						signed.id, self.set_id
+					))
+				})?;
+			// count every authority once
+			if !seen.insert(signed.id.clone()) {
+				continue
+			}
+			if !check_message_signature(
+				&Message::Precommit(signed.precommit.clone()),
+				&signed.id,
+				&signed.signature,
+				justification.round,
+				self.set_id,
+			) {
+				return Err(Error::FinalityError(format!(
+					"invalid precommit signature of {:?} in set #{:}",
+					signed.id, self.set_id
+				)))
+			}
+			if !is_descendant(signed.precommit.target_hash, target, &ancestry) {
+				return Err(Error::FinalityError(format!(
+					"precommit target {:} is not a descendant of {:}",
+					signed.precommit.target_hash, target
+				)))
+			}
+			signed_weight += weight;
+		}
+
+		let total_weight: u64 = self.authorities.iter().map(|(_, weight)| weight).sum();
+		// same as `finality_grandpa::VoterSet::threshold`
+		let threshold = total_weight - total_weight.saturating_sub(1) / 3;
+		if signed_weight < threshold {
+			return Err(Error::FinalityError(format!(
+				"justification weight {:} is under threshold {:}",
+				signed_weight, threshold
+			)))
+		}
+
+		Ok(())
+	}
+
+	// keep the changes scheduled in `headers` and enact the ones due by block `finalized`. A
+	// forced change can not be followed with justifications alone, it fails the tracker
+	fn apply_authority_changes<'a>(
+		&mut self,
+		finalized: BlockNumber,
+		headers: impl Iterator<Item = &'a Header>,
+	) -> Result<(), Error> {
+		for h in headers {
+			// scanned already
+			if self.next.map_or(false, |next| *h.number() < next) {
+				continue
+			}
+			let change = h.digest().convert_first(|item| {
+				item.try_to::<ConsensusLog<BlockNumber>>(OpaqueDigestItemId::Consensus(
+					&GRANDPA_ENGINE_ID,
+				))
+			});
+			match change {
+				Some(ConsensusLog::ScheduledChange(change)) => {
+					self.pending.push((h.number() + change.delay, change.next_authorities));
+				},
+				Some(ConsensusLog::ForcedChange(..)) =>
+					return Err(Error::ForcedAuthorityChange(*h.number())),
+				_ => {},
+			}
+		}
+
+		self.pending.sort_by_key(|(enacted_at, _)| *enacted_at);
+		while self.pending.first().map_or(false, |(enacted_at, _)| *enacted_at <= finalized) {
+			let (enacted_at, authorities) = self.pending.remove(0);
+			self.set_id += 1;
+			self.authorities = authorities;
+			log::info!(
+				target: KILT_LOG_TARGET,
+				"grandpa authority set #{:} enacted at block #{:}",
+				self.set_id,
+				enacted_at
+			);
+		}
+		Ok(())
+	}
+}
+
+// fetch the header of `hash` and check that it really hashes to it
+async fn fetch_header(client: &KiltClient, hash: Hash) -> Result<Header, Error> {
+	let header = client
+		.header(hash)
+		.await?
+		.ok_or_else(|| Error::FinalityError(format!("unknown finalized block {:}", hash)))?;
+	if header.hash() != hash {
+		return Err(Error::FinalityError(format!(
+			"header returned for {:} hashes to {:}",
+			hash,
+			header.hash()
+		)))
+	}
+	Ok(header)
+}
+
+// `unknown_headers` holds the headers in the range (header; justified], check they link
+// the justified block back to `header`
+fn check_ancestry(
+	header: &Header,
+	justified: Hash,
+	unknown_headers: &[Header],
+) -> Result<(), Error> {
+	let headers: HashMap<Hash, &Header> = unknown_headers.iter().map(|h| (h.hash(), h)).collect();
+	let target = header.hash();
+	let mut cursor = justified;
+	for _ in 0..=headers.len() {
+		if cursor == target {
+			return Ok(())
+		}
+		match headers.get(&cursor) {
+			Some(h) => cursor = *h.parent_hash(),
+			None => break,
+		}
+	}
+	Err(Error::FinalityError(format!(
+		"block {:} is not an ancestor of justified block {:}",
+		target, justified
+	)))
+}
+
+fn is_descendant(mut block: Hash, ancestor: Hash, ancestry: &HashMap<Hash, &Header>) -> bool {
+	for _ in 0..=ancestry.len() {
+		if block == ancestor {
+			return true
+		}
+		match ancestry.get(&block) {
+			Some(h) => block = *h.parent_hash(),
+			None => return false,
+		}
+	}
+	false
+}
+
+#[cfg(test)]
+mod tests {
+	use codec::Encode;
+	use sp_finality_grandpa::ScheduledChange;
+	use sp_runtime::{Digest, DigestItem};
+
+	use super::*;
+
+	fn header(number: BlockNumber, parent_hash: Hash) -> Header {
+		Header::new(number, Hash::default(), Hash::default(), parent_hash, Digest::default())
+	}
+
+	#[test]
+	fn ancestry_should_link_justified_block() {
+		let h1 = header(1, Hash::default());
+		let h2 = header(2, h1.hash());
+		let h3 = header(3, h2.hash());
+
+		assert!(check_ancestry(&h1, h3.hash(), &[h2.clone(), h3.clone()]).is_ok());
+		assert!(check_ancestry(&h1, h1.hash(), &[]).is_ok());
+		// h2 is missing so the chain is broken
+		assert!(check_ancestry(&h1, h3.hash(), &[h3.clone()]).is_err());
+	}
+
+	#[test]
+	fn scheduled_change_should_be_enacted_when_due() {
+		let mut tracker = FinalityTracker::new(0, vec![]);
+		let next_authorities = vec![(AuthorityId::default(), 1)];
+		let change = ConsensusLog::ScheduledChange(ScheduledChange {
+			next_authorities: next_authorities.clone(),
+			delay: 10,
+		});
+		let mut h5 = header(5, Hash::default());
+		h5.digest =
+			Digest { logs: vec![DigestItem::Consensus(GRANDPA_ENGINE_ID, change.encode())] };
+
+		// scheduled in a batch before it is enacted
+		tracker.apply_authority_changes(8, std::iter::once(&h5)).unwrap();
+		tracker.next = Some(9);
+		assert_eq!(tracker.set_id, 0);
+
+		// the header is not scanned again, but the change is still enacted
+		tracker.apply_authority_changes(20, std::iter::once(&h5)).unwrap();
+		assert_eq!(tracker.set_id, 1);
+		assert_eq!(tracker.authorities, next_authorities);
+		assert!(tracker.pending.is_empty());
+	}
+
+	#[test]
+	fn forced_change_should_fail() {
+		let mut tracker = FinalityTracker::new(0, vec![]);
+		let change = ConsensusLog::ForcedChange(
+			3,
+			ScheduledChange { next_authorities: vec![(AuthorityId::default(), 1)], delay: 0 },
+		);
+		let mut h5 = header(5, Hash::default());
+		h5.digest =
+			Digest { logs: vec![DigestItem::Consensus(GRANDPA_ENGINE_ID, change.encode())] };
+
+		assert!(matches!(
+			tracker.apply_authority_changes(8, std::iter::once(&h5)),
+			Err(Error::ForcedAuthorityChange(5))
+		));
+		assert_eq!(tracker.set_id, 0);
+	}
+
+	#[test]
+	fn justification_without_precommits_should_fail() {
+		let tracker = FinalityTracker::new(0, vec![]);
+		let target = header(1, Hash::default()).hash();
+		let justification = GrandpaJustification {
+			round: 1,
+			commit: Commit { target_hash: Hash::default(), target_number: 1, precommits: vec![] },
+			votes_ancestries: vec![],
+		};
+		// wrong target
+		assert!(tracker.verify_justification(target, &justification).is_err());
+
+		let tracker = FinalityTracker::new(0, vec![(AuthorityId::default(), 1)]);
+		let justification = GrandpaJustification {
+			commit: Commit { target_hash: target, ..justification.commit },
+			..justification
+		};
+		// no weight signed
+		assert!(tracker.verify_justification(target, &justification).is_err());
+	}
+}
@@ -1,99 +1,400 @@
+use std::{collections::HashMap, future::Future};
+
 use jsonrpsee::types::Error as RpcError;
 
+pub use cache::AttestationCache;
+use delegation::DelegationNodes;
+use did::{DidStatus, DidStatuses};
+pub use finality::FinalityTracker;
 use keeper_primitives::{
 	kilt::{
-		get_attestation_storage_key, Attestation, Error, KiltClient, KILT_LOG_TARGET,
-		KILT_MAX_RETRY_TIMES,
+		get_attestation_storage_key, Attestation, AuthorizationId, Error, KiltClient, KiltConfig,
+		RejectReason, KILT_LOG_TARGET, KILT_MAX_RETRY_TIMES,
 	},
-	Decode, Hash, Result, VerifyResult,
+	monitor::MonitorSender,
+	Hash, Result, StorageData, StorageKey, VerifyResult,
 };
+pub use policy::AttesterPolicy;
+pub use runtime::{AttestationLayout, RuntimeDecoder};
 pub use task::task_attestation;
 
+mod cache;
+mod delegation;
+mod did;
+mod finality;
+mod policy;
+mod proof;
+mod runtime;
 mod task;
 
-pub async fn filter(client: &KiltClient, result: Vec<VerifyResult>) -> Result<Vec<VerifyResult>> {
-	let mut v = vec![];
-	for i in result {
-		// query attestation details from kilt
-		let maybe_attest = query_attestation(client, i.root_hash.into())
-			.await
-			.map_err(|e| (i.number, e.into()))?;
+// local state of the kilt task which lives across messages
+pub struct KiltState {
+	// set if attestations must be checked against storage proofs
+	pub finality: Option<FinalityTracker>,
+	// attestation layout of the current kilt runtime
+	pub runtime: RuntimeDecoder,
+	// trusted attesters of each ctype
+	pub policy: AttesterPolicy,
+	// valid attestations watched for revocation, if enabled
+	pub cache: Option<AttestationCache>,
+	// where rejected credentials are reported
+	pub monitor: Option<MonitorSender>,
+}
 
-		let mut v_update = i.clone();
+impl KiltState {
+	pub async fn new(client: &KiltClient, config: &KiltConfig) -> std::result::Result<Self, Error> {
+		let finality = if config.verify_storage_proof {
+			Some(
+				FinalityTracker::bootstrap(
+					client,
+					config.finality_checkpoint.as_ref(),
+					config.insecure_trust_node,
+				)
+				.await?,
+			)
+		} else {
+			None
+		};
+		let cache = match &config.attestation_cache {
+			Some(cache_config) => Some(AttestationCache::new(cache_config).await?),
+			None => None,
+		};
+		Ok(KiltState {
+			finality,
+			runtime: RuntimeDecoder::default(),
+			policy: AttesterPolicy::load(config)?,
+			cache,
+			monitor: None,
+		})
+	}
+}
 
-		// submit to moonbeam if and only if
-		// the attestation neither empty or revoked
-		if maybe_attest.is_some() &&
-			Ok(()) == v_update.update_from_attestation(maybe_attest.unwrap())
+pub async fn filter(
+	client: &KiltClient,
+	state: &mut KiltState,
+	result: Vec<VerifyResult>,
+) -> Result<Vec<VerifyResult>> {
+	match filter_with_current_node(client, state, result.clone()).await {
+		// a node failing the proof or finality check is asked no more, the batch is checked
+		// once more against the next endpoint. If that one fails as well the error surfaces
+		Err((_, keeper_primitives::Error::KiltError(e)))
+			if e.is_untrusted() && client.fail_over() =>
 		{
-			log::info!(
-				target: KILT_LOG_TARGET,
-				"roothash: {:} | in block #{:?} has been attested",
-				hex::encode(i.root_hash),
-				i.number
-			);
-			v.push(v_update)
-		} else {
 			log::warn!(
-                target: KILT_LOG_TARGET,
-                "attestaion is not valid for this root_hash|root_hash:{:}|data owner:{:}|number:{:?}",
-				hex::encode(i.root_hash), hex::encode(i.data_owner.0),
-				i.number
+				target: KILT_LOG_TARGET,
+				"kilt node answer is not trusted, check the batch with the next endpoint, err: {:?}",
+				e
 			);
-			// TODO: notice the server that user's credential is not valid on kilt
+			filter_with_current_node(client, state, result).await
+		},
+		res => res,
+	}
+}
+
+async fn filter_with_current_node(
+	client: &KiltClient,
+	state: &mut KiltState,
+	result: Vec<VerifyResult>,
+) -> Result<Vec<VerifyResult>> {
+	let start = result.first().and_then(|r| r.number);
+
+	// pick up the changes of the trusted attester file
+	state.policy.reload_if_changed();
+
+	// pin one block for the whole batch. If storage proofs are required, it is a finalized block
+	// whose state root is covered by a grandpa justification, otherwise the best block
+	let (at, state_root) = match state.finality.as_mut() {
+		Some(finality) => {
+			let (at, state_root) =
+				finality.finalized_state_root(client).await.map_err(|e| (start, e.into()))?;
+			(at, Some(state_root))
+		},
+		None => {
+			let at = request_with_retry(|| client.block_hash(None))
+				.await
+				.map_err(|e| (start, Error::from(e).into()))?
+				.ok_or_else(|| {
+					(start, Error::UnexpectedResponse("no best block".to_owned()).into())
+				})?;
+			(at, None)
+		},
+	};
+
+	// the storage layout may change with runtime upgrades, pick the decoder matching
+	// the runtime of the queried block
+	let layout = state.runtime.layout(client, Some(at)).await.map_err(|e| (start, e.into()))?;
+
+	// query attestation details of the whole batch from kilt, except those still cached. With
+	// storage proofs, only the ones proven at the pinned block are taken from the cache
+	let root_hashes: Vec<Hash> = result.iter().map(|i| i.root_hash.into()).collect();
+	let proven_at = state_root.map(|_| at);
+	let mut cached = HashMap::new();
+	if let Some(cache) = &state.cache {
+		for root_hash in &root_hashes {
+			if let Some(attestation) = cache.get(*root_hash, proven_at).await {
+				cached.insert(*root_hash, attestation);
+			}
+		}
+	}
+	let missing: Vec<Hash> =
+		root_hashes.iter().filter(|h| !cached.contains_key(*h)).cloned().collect();
+	let fetched = if missing.is_empty() {
+		vec![]
+	} else {
+		query_attestations(client, layout, &missing, at, state_root)
+			.await
+			.map_err(|e| (start, e.into()))?
+	};
+	let fetched: HashMap<Hash, Option<Attestation>> = missing.into_iter().zip(fetched).collect();
+	if let Some(cache) = &state.cache {
+		let valid = fetched.iter().filter_map(|(h, a)| a.clone().map(|a| (*h, a))).collect();
+		cache.insert(valid, layout, proven_at).await;
+	}
+	let attestations: Vec<Option<Attestation>> = root_hashes
+		.iter()
+		.map(|h| cached.get(h).or_else(|| fetched.get(h).and_then(|a| a.as_ref())).cloned())
+		.collect();
+
+	// fetch the hierarchies of delegated attestations up to their roots
+	let delegation_ids: Vec<Hash> = attestations
+		.iter()
+		.flatten()
+		.filter_map(|a| match a.authorization_id {
+			Some(AuthorizationId::Delegation(id)) => Some(id),
+			None => None,
+		})
+		.collect();
+	// none if the delegations can not be read with this layout, only the delegated
+	// attestations are rejected then
+	let delegations = if delegation_ids.is_empty() {
+		Some(DelegationNodes::new())
+	} else if layout != AttestationLayout::AuthorizationId {
+		log::warn!(
+			target: KILT_LOG_TARGET,
+			"delegated attestations are not supported with layout {:?}, reject {:} of them",
+			layout,
+			delegation_ids.len()
+		);
+		None
+	} else {
+		Some(
+			delegation::query_delegation_nodes(client, delegation_ids, at, state_root)
+				.await
+				.map_err(|e| (start, e.into()))?,
+		)
+	};
+
+	// resolve the attesters to their did and web3name
+	let attesters = attestations.iter().flatten().map(|a| a.attester.clone()).collect();
+	let dids = did::query_dids(client, attesters, at, state_root)
+		.await
+		.map_err(|e| (start, e.into()))?;
+
+	let mut v = vec![];
+	for (i, maybe_attest) in result.into_iter().zip(attestations) {
+		let mut v_update = i.clone();
+
+		// the credential passes if and only if the attestation is neither empty nor revoked,
+		// matches the claims of the user, the did of the attester is not deleted and its
+		// delegation hierarchy is valid
+		match check_attestation(
+			&mut v_update,
+			maybe_attest,
+			&state.policy,
+			&dids,
+			delegations.as_ref(),
+		) {
+			Ok(()) => {
+				log::info!(
+					target: KILT_LOG_TARGET,
+					"roothash: {:} | in block #{:?} has been attested | attester: {:?} | delegations: {:?}",
+					hex::encode(i.root_hash),
+					i.number,
+					v_update.attester_identity,
+					v_update.delegation_chain.iter().map(hex::encode).collect::<Vec<_>>()
+				);
+				v.push(v_update)
+			},
+			Err(reason) => {
+				log::warn!(
+					target: KILT_LOG_TARGET,
+					"attestaion is not valid for this root_hash|root_hash:{:}|data owner:{:}|number:{:?}|reason:{:}",
+					hex::encode(i.root_hash),
+					hex::encode(i.data_owner.0),
+					i.number,
+					reason
+				);
+				// still submit it with a negative outcome, so that the
+				// contract and the dApp learn the credential failed
+				v_update.reject(reason);
+				v.push(v_update)
+			},
 		}
 	}
 	Ok(v)
 }
 
+// check the verify result against its attestation, or tell why it is rejected
+fn check_attestation(
+	v: &mut VerifyResult,
+	maybe_attest: Option<Attestation>,
+	policy: &AttesterPolicy,
+	dids: &DidStatuses,
+	delegations: Option<&DelegationNodes>,
+) -> std::result::Result<(), RejectReason> {
+	let attest = maybe_attest.ok_or(RejectReason::NotAttested)?;
+	v.match_attestation(&attest)?;
+	match dids.get(&attest.attester) {
+		Some(DidStatus::Active(identity)) => v.attester_identity = Some(identity.clone()),
+		Some(DidStatus::Deleted) => return Err(RejectReason::DidDeleted(v.attester)),
+		_ => {},
+	}
+	policy.check(&v.c_type, &v.attester, v.attester_identity.as_ref())?;
+	if let Some(AuthorizationId::Delegation(id)) = attest.authorization_id {
+		let delegations = delegations.ok_or(RejectReason::DelegationUnsupported(id.0))?;
+		let chain = delegation::check_delegation(delegations, id, &attest.attester)?;
+		v.delegation_chain = chain.into_iter().map(|id| id.0).collect();
+	}
+	Ok(())
+}
+
 /// query attestation info from kilt network
 /// TODO: handle kilt error??
 pub async fn query_attestation(
 	client: &KiltClient,
+	layout: AttestationLayout,
 	root_hash: Hash,
 ) -> std::result::Result<Option<Attestation>, Error> {
 	let storage_key = get_attestation_storage_key::<Hash>(root_hash);
+	// connect to kilt and query attestation storage
+	let maybe_attestation_details =
+		request_with_retry(|| client.request_storage(&storage_key, None)).await?;
+
+	decode_attestation(layout, root_hash, maybe_attestation_details)
+}
+
+/// query the attestations of all `root_hashes` at the same block `at` with one request,
+/// the results are in the same order as `root_hashes`. If `state_root` is given the values
+/// are only accepted if their storage proof matches it.
+pub async fn query_attestations(
+	client: &KiltClient,
+	layout: AttestationLayout,
+	root_hashes: &[Hash],
+	at: Hash,
+	state_root: Option<Hash>,
+) -> std::result::Result<Vec<Option<Attestation>>, Error> {
+	let keys = attestation_storage_keys(root_hashes);
+	let values = query_storage(client, &keys, at, state_root).await?;
+
+	root_hashes
+		.iter()
+		.map(|root_hash| {
+			let storage_key = get_attestation_storage_key::<Hash>(*root_hash);
+			let maybe_attestation_details = values.get(&storage_key).cloned().flatten();
+			decode_attestation(layout, *root_hash, maybe_attestation_details)
+		})
+		.collect()
+}
+
+// query `keys` at block `at`, with a checked storage proof if `state_root` is given. A key left
+// out by the node is not taken as empty storage, it is fetched on its own instead
+pub(crate) async fn query_storage(
+	client: &KiltClient,
+	keys: &[StorageKey],
+	at: Hash,
+	state_root: Option<Hash>,
+) -> std::result::Result<HashMap<StorageKey, Option<StorageData>>, Error> {
+	let mut values: HashMap<StorageKey, Option<StorageData>> = match state_root {
+		Some(state_root) => {
+			let read_proof = request_with_retry(|| client.request_read_proof(keys, at)).await?;
+			keys.iter()
+				.cloned()
+				.zip(proof::check_read_proof(state_root, read_proof, keys)?)
+				.collect()
+		},
+		None => request_with_retry(|| client.request_storage_at(keys, at))
+			.await?
+			.into_iter()
+			.collect(),
+	};
+	let missing: Vec<StorageKey> =
+		keys.iter().filter(|k| !values.contains_key(*k)).cloned().collect();
+	for key in missing {
+		log::warn!(
+			target: KILT_LOG_TARGET,
+			"storage key 0x{:} missing at block {:}, fetch it on its own",
+			hex::encode(&key.0),
+			at
+		);
+		let value = request_with_retry(|| client.request_storage(&key, Some(at))).await?;
+		values.insert(key, value);
+	}
+	Ok(values)
+}
+
+// storage keys of `root_hashes`, without duplicates
+fn attestation_storage_keys(root_hashes: &[Hash]) -> Vec<StorageKey> {
+	let mut keys: Vec<StorageKey> =
+		root_hashes.iter().map(|h| get_attestation_storage_key::<Hash>(*h)).collect();
+	keys.sort();
+	keys.dedup();
+	keys
+}
+
+// decode fetched storage data
+fn decode_attestation(
+	layout: AttestationLayout,
+	root_hash: Hash,
+	maybe_attestation_details: Option<StorageData>,
+) -> std::result::Result<Option<Attestation>, Error> {
+	let maybe_attestation: Option<Attestation> = match maybe_attestation_details {
+		Some(data) => Some(layout.decode(&data.0)?),
+		None => None,
+	};
+
+	log::info!(
+		target: KILT_LOG_TARGET,
+		"Kilt query result of roothash: [{:}] is {:?}",
+		hex::encode(root_hash),
+		maybe_attestation
+	);
+
+	Ok(maybe_attestation)
+}
+
+// retry the kilt rpc request on timeout and transport error, the client has
+// switched to the next endpoint by then
+async fn request_with_retry<T, F, Fut>(request: F) -> std::result::Result<T, RpcError>
+where
+	F: Fn() -> Fut,
+	Fut: Future<Output = std::result::Result<T, RpcError>>,
+{
 	let mut times = 0;
-	let maybe_attestation_details = loop {
-		// connect to kilt and query attestation storage
-		match client.request_storage(&storage_key, None).await {
-			Ok(details) => break details,
+	loop {
+		match request().await {
+			Ok(res) => return Ok(res),
 			Err(e) => {
 				match e {
-					RpcError::RequestTimeout | RpcError::Transport(_) => {
+					RpcError::RequestTimeout |
+					RpcError::Transport(_) |
+					RpcError::RestartNeeded(_) =>
 						if times < KILT_MAX_RETRY_TIMES {
 							times += 1;
 							log::warn!(
 								target: KILT_LOG_TARGET,
-								"query kilt storage timeout, retry {:}/{:}",
+								"query kilt storage failed, retry {:}/{:}",
 								times,
 								KILT_MAX_RETRY_TIMES
 							);
 							continue
-						}
-					},
+						},
 
 					_ => {},
 				}
-				return Err(e)?
+				return Err(e)
 			},
 		}
-	};
-
-	// decode fetched storage data
-	let maybe_attestation: Option<Attestation> = match maybe_attestation_details {
-		Some(data) => Some(Decode::decode(&mut data.0.as_slice())?),
-		None => None,
-	};
-
-	log::info!(
-		target: KILT_LOG_TARGET,
-		"Kilt query result of roothash: [{:}] is {:?}",
-		hex::encode(root_hash),
-		maybe_attestation
-	);
-
-	Ok(maybe_attestation)
+	}
 }
 
 #[cfg(test)]
@@ -102,7 +403,7 @@ mod tests {
 
 	use keeper_primitives::{kilt::Attestation, KiltClient};
 
-	use crate::{query_attestation, Hash};
+	use crate::{query_attestation, AttestationLayout, Hash};
 
 	#[inline]
 	fn attest_exp() -> Attestation {
@@ -133,13 +434,17 @@ mod tests {
 		let right_root_hash =
 			Hash::from_str("af6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336933")
 				.expect("root hash from string error");
-		let maybe_right_attest = query_attestation(&kilt_client, right_root_hash).await;
+		let maybe_right_attest =
+			query_attestation(&kilt_client, AttestationLayout::AuthorizationId, right_root_hash)
+				.await;
 		assert_eq!(maybe_right_attest.unwrap().unwrap(), attest_exp);
 
 		let empty_root_hash =
 			Hash::from_str("7b6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336966")
 				.expect("root hash from string error");
-		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash).await;
+		let maybe_empty_attest =
+			query_attestation(&kilt_client, AttestationLayout::AuthorizationId, empty_root_hash)
+				.await;
 		assert_eq!(maybe_empty_attest.unwrap(), None);
 	}
 
@@ -152,7 +457,9 @@ mod tests {
 		let empty_root_hash =
 			Hash::from_str("7b6e8c774b0f7409743f7e28e29fd3196d0eee72c66e57c550302abea4336966")
 				.expect("root hash from string error");
-		let maybe_empty_attest = query_attestation(&kilt_client, empty_root_hash).await;
+		let maybe_empty_attest =
+			query_attestation(&kilt_client, AttestationLayout::AuthorizationId, empty_root_hash)
+				.await;
 		assert_eq!(maybe_empty_attest.unwrap(), None);
 	}
 }
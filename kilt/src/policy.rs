@@ -0,0 +1,216 @@
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	fs::File,
+	path::PathBuf,
+	time::SystemTime,
+};
+
+use sp_core::crypto::{AccountId32, Ss58Codec};
+
+use keeper_primitives::{
+	kilt::{AttesterIdentity, Error, KiltConfig, RejectReason, KILT_LOG_TARGET},
+	Bytes32,
+};
+
+const DID_PREFIX: &str = "did:kilt:";
+const WEB3_NAME_PREFIX: &str = "w3n:";
+
+// ctype hash => attesters, as written in config, e.g.
+// { "0x7f2e...1a18": ["did:kilt:4pf1...WHR2", "0x4cfd...ff01", "w3n:john_doe"] }
+pub type TrustedAttesters = BTreeMap<String, Vec<String>>;
+
+// the attesters trusted for one ctype
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Trusted {
+	accounts: HashSet<Bytes32>,
+	web3names: HashSet<String>,
+}
+
+impl Trusted {
+	fn extend(&mut self, other: Trusted) {
+		self.accounts.extend(other.accounts);
+		self.web3names.extend(other.web3names);
+	}
+}
+
+/// Which attesters are trusted to attest each ctype. Without any entry every attester is
+/// trusted, otherwise only the listed (ctype, attester) pairs are.
+#[derive(Clone, Debug, Default)]
+pub struct AttesterPolicy {
+	trusted: HashMap<Bytes32, Trusted>,
+	// entries from the keeper config, never reloaded
+	inline: TrustedAttesters,
+	// the policy file and its modification time when last loaded
+	file: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+impl AttesterPolicy {
+	pub fn load(config: &KiltConfig) -> Result<Self, Error> {
+		let mut policy = AttesterPolicy {
+			inline: config.trusted_attesters.clone(),
+			file: config.trusted_attesters_file.clone().map(|path| (path, None)),
+			..Default::default()
+		};
+		policy.reload()?;
+		Ok(policy)
+	}
+
+	/// re-read the policy file if it has been modified since it was last loaded,
+	/// a broken file is logged and the current policy kept
+	pub fn reload_if_changed(&mut self) {
+		let changed = match &self.file {
+			Some((path, loaded_at)) => modified_at(path) != *loaded_at,
+			None => false,
+		};
+		if changed {
+			if let Err(e) = self.reload() {
+				log::error!(
+					target: KILT_LOG_TARGET,
+					"fail to reload trusted attesters, keep the current policy. err: {:?}",
+					e
+				);
+			}
+		}
+	}
+
+	fn reload(&mut self) -> Result<(), Error> {
+		let mut trusted = parse_trusted_attesters(&self.inline)?;
+		if let Some((path, loaded_at)) = &mut self.file {
+			let modified = modified_at(path);
+			let file = File::open(&path).map_err(|e| {
+				Error::InvalidAttesterPolicy(format!("open {:}: {:}", path.display(), e))
+			})?;
+			let from_file: TrustedAttesters = serde_json::from_reader(file)?;
+			for (c_type, attesters) in parse_trusted_attesters(&from_file)? {
+				trusted.entry(c_type).or_default().extend(attesters);
+			}
+			*loaded_at = modified;
+		}
+
+		log::info!(
+			target: KILT_LOG_TARGET,
+			"trusted attesters loaded for {:} ctypes",
+			trusted.len()
+		);
+		self.trusted = trusted;
+		Ok(())
+	}
+
+	/// the attester is trusted by its account or did, or by the web3name of its did
+	pub fn check(
+		&self,
+		c_type: &Bytes32,
+		attester: &Bytes32,
+		identity: Option<&AttesterIdentity>,
+	) -> Result<(), RejectReason> {
+		if self.trusted.is_empty() {
+			return Ok(())
+		}
+		let web3name = identity.and_then(|i| i.web3name.as_ref());
+		match self.trusted.get(c_type) {
+			Some(trusted)
+				if trusted.accounts.contains(attester) ||
+					web3name.map(|n| trusted.web3names.contains(n)).unwrap_or(false) =>
+				Ok(()),
+			_ => Err(RejectReason::UntrustedAttester { c_type: *c_type, attester: *attester }),
+		}
+	}
+}
+
+fn modified_at(path: &PathBuf) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn parse_trusted_attesters(raw: &TrustedAttesters) -> Result<HashMap<Bytes32, Trusted>, Error> {
+	let mut trusted = HashMap::<Bytes32, Trusted>::new();
+	for (c_type, attesters) in raw {
+		let entry = trusted.entry(parse_bytes32(c_type)?).or_default();
+		for attester in attesters {
+			match attester.strip_prefix(WEB3_NAME_PREFIX) {
+				Some(name) => entry.web3names.insert(name.to_owned()),
+				None => entry.accounts.insert(parse_attester(attester)?),
+			};
+		}
+	}
+	Ok(trusted)
+}
+
+// an attester is a kilt account or full did, in hex or ss58. Web3names are not accepted here
+pub fn parse_attester(attester: &str) -> Result<Bytes32, Error> {
+	let account = attester.strip_prefix(DID_PREFIX).unwrap_or(attester);
+	if account.starts_with("0x") {
+		return parse_bytes32(account)
+	}
+	AccountId32::from_ss58check(account).map(|a| a.into()).map_err(|e| {
+		Error::InvalidAttesterPolicy(format!("invalid attester {:}: {:?}", attester, e))
+	})
+}
+
+fn parse_bytes32(s: &str) -> Result<Bytes32, Error> {
+	let raw = hex::decode(s.strip_prefix("0x").unwrap_or(s))
+		.map_err(|e| Error::InvalidAttesterPolicy(format!("invalid hex {:}: {:}", s, e)))?;
+	raw.try_into()
+		.map_err(|_| Error::InvalidAttesterPolicy(format!("{:} is not 32 bytes long", s)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const C_TYPE: &str = "0x7f2ef721b292b9b7d678e9f82ab010e139600558df805bbc61a0041e60b61a18";
+	const ATTESTER: &str = "0x4cfd2e722b370b1015343a27c9789815d803fdb1840aaa0406a26be55a95ff01";
+
+	#[test]
+	fn attester_policy_should_work() {
+		let c_type = parse_bytes32(C_TYPE).unwrap();
+		let attester = parse_attester(ATTESTER).unwrap();
+		let other = [0u8; 32];
+
+		// no policy, everyone is trusted
+		let policy = AttesterPolicy::default();
+		assert!(policy.check(&c_type, &other, None).is_ok());
+
+		let mut inline = TrustedAttesters::new();
+		inline.insert(C_TYPE.to_owned(), vec![format!("{:}{:}", DID_PREFIX, ATTESTER)]);
+		let policy = AttesterPolicy {
+			trusted: parse_trusted_attesters(&inline).unwrap(),
+			..Default::default()
+		};
+		assert!(policy.check(&c_type, &attester, None).is_ok());
+		assert_eq!(
+			policy.check(&c_type, &other, None),
+			Err(RejectReason::UntrustedAttester { c_type, attester: other })
+		);
+		// unlisted ctype
+		assert!(policy.check(&other, &attester, None).is_err());
+	}
+
+	#[test]
+	fn trust_attester_by_web3name_should_work() {
+		let c_type = parse_bytes32(C_TYPE).unwrap();
+		let attester = [0u8; 32];
+
+		let mut inline = TrustedAttesters::new();
+		inline.insert(C_TYPE.to_owned(), vec![format!("{:}john_doe", WEB3_NAME_PREFIX)]);
+		let policy = AttesterPolicy {
+			trusted: parse_trusted_attesters(&inline).unwrap(),
+			..Default::default()
+		};
+
+		let mut identity = AttesterIdentity {
+			did: "did:kilt:4pf1".to_owned(),
+			web3name: Some("john_doe".to_owned()),
+		};
+		assert!(policy.check(&c_type, &attester, Some(&identity)).is_ok());
+		identity.web3name = None;
+		assert!(policy.check(&c_type, &attester, Some(&identity)).is_err());
+		assert!(policy.check(&c_type, &attester, None).is_err());
+	}
+
+	#[test]
+	fn parse_ss58_attester_should_work() {
+		let attester = parse_attester("did:kilt:4pf1fzQjRnNcLxKDn6vE1R3nBEu1MUbW4Wz63uKFQUD7WHR2");
+		assert!(attester.is_ok());
+		assert!(parse_attester("did:kilt:light:004pf1fzQjRnNcLxKDn6vE1R3nBEu1MUbW4Wz").is_err());
+	}
+}
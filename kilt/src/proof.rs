@@ -0,0 +1,33 @@
+use sp_runtime::traits::BlakeTwo256;
+use sp_state_machine::read_proof_check;
+use sp_trie::StorageProof;
+
+use keeper_primitives::{
+	kilt::{Error, ReadProof},
+	Hash, StorageData, StorageKey,
+};
+
+/// check the `state_getReadProof` response against a trusted state root and
+/// return the values stored under `keys`, in the same order
+pub fn check_read_proof(
+	state_root: Hash,
+	read_proof: ReadProof,
+	keys: &[StorageKey],
+) -> Result<Vec<Option<StorageData>>, Error> {
+	let proof = StorageProof::new(read_proof.proof.into_iter().map(|node| node.0).collect());
+	let values =
+		read_proof_check::<BlakeTwo256, _>(state_root, proof, keys.iter().map(|k| k.0.as_slice()))
+			.map_err(|e| Error::InvalidStorageProof(format!("{:?}", e)))?;
+
+	// every key must be covered by the proof, an absent value is proven as `None`
+	keys.iter()
+		.map(|key| {
+			values.get(&key.0).cloned().map(|v| v.map(StorageData)).ok_or_else(|| {
+				Error::InvalidStorageProof(format!(
+					"key 0x{:} is not in the proof",
+					hex::encode(&key.0)
+				))
+			})
+		})
+		.collect()
+}
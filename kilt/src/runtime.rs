@@ -0,0 +1,248 @@
+use codec::Decode;
+use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed, StorageEntryType};
+use scale_info::TypeDef;
+use sp_runtime::AccountId32 as AccountId;
+
+use keeper_primitives::{
+	kilt::{
+		Attestation, AttestationDetails, AuthorizationId, Balance, Deposit, Error, KiltClient,
+		RuntimeVersion, ATTESTATION_PALLET_PREFIX, ATTESTATION_STORAGE_PREFIX, KILT_LOG_TARGET,
+	},
+	Hash,
+};
+
+/// The layouts `Attestation::Attestations` has been stored with across kilt runtime upgrades,
+/// picked by the spec version of the runtime. The field names of the storage value in the
+/// runtime metadata must agree with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttestationLayout {
+	// { ctype_hash, attester, delegation_id, revoked }
+	DelegationId,
+	// { ctype_hash, attester, delegation_id, revoked, deposit }
+	DelegationIdWithDeposit,
+	// { ctype_hash, attester, authorization_id, revoked, deposit }
+	AuthorizationId,
+}
+
+// the first spec version of each layout, in ascending order
+const LAYOUT_SINCE: &[(u32, AttestationLayout)] = &[
+	(0, AttestationLayout::DelegationId),
+	(23, AttestationLayout::DelegationIdWithDeposit),
+	(10700, AttestationLayout::AuthorizationId),
+];
+
+// the field names of each layout in the runtime metadata
+const KNOWN_LAYOUTS: &[(&[&str], AttestationLayout)] = &[
+	(&["ctype_hash", "attester", "delegation_id", "revoked"], AttestationLayout::DelegationId),
+	(
+		&["ctype_hash", "attester", "delegation_id", "revoked", "deposit"],
+		AttestationLayout::DelegationIdWithDeposit,
+	),
+	(
+		&["ctype_hash", "attester", "authorization_id", "revoked", "deposit"],
+		AttestationLayout::AuthorizationId,
+	),
+];
+
+#[derive(Decode)]
+struct DelegationIdDetails {
+	ctype_hash: Hash,
+	attester: AccountId,
+	delegation_id: Option<Hash>,
+	revoked: bool,
+}
+
+#[derive(Decode)]
+struct DelegationIdWithDepositDetails {
+	ctype_hash: Hash,
+	attester: AccountId,
+	delegation_id: Option<Hash>,
+	revoked: bool,
+	deposit: Deposit<AccountId, Balance>,
+}
+
+impl AttestationLayout {
+	/// the layout attestations are stored with by runtime `spec_version`
+	pub fn from_spec_version(spec_version: u32) -> Self {
+		LAYOUT_SINCE
+			.iter()
+			.rev()
+			.find(|(since, _)| *since <= spec_version)
+			.map(|(_, layout)| *layout)
+			.unwrap_or(AttestationLayout::DelegationId)
+	}
+
+	/// decode the raw storage value into the latest `Attestation` type
+	pub fn decode(&self, data: &[u8]) -> Result<Attestation, Error> {
+		let input = &mut &data[..];
+		let attestation = match self {
+			AttestationLayout::DelegationId => {
+				let d = DelegationIdDetails::decode(input)?;
+				AttestationDetails {
+					ctype_hash: d.ctype_hash,
+					deposit: Deposit { owner: d.attester.clone(), amount: 0 },
+					attester: d.attester,
+					authorization_id: d.delegation_id.map(AuthorizationId::Delegation),
+					revoked: d.revoked,
+				}
+			},
+			AttestationLayout::DelegationIdWithDeposit => {
+				let d = DelegationIdWithDepositDetails::decode(input)?;
+				AttestationDetails {
+					ctype_hash: d.ctype_hash,
+					attester: d.attester,
+					authorization_id: d.delegation_id.map(AuthorizationId::Delegation),
+					revoked: d.revoked,
+					deposit: d.deposit,
+				}
+			},
+			AttestationLayout::AuthorizationId => Attestation::decode(input)?,
+		};
+
+		// trailing bytes mean the layout does not match the runtime
+		if !input.is_empty() {
+			return Err(Error::UnsupportedRuntime(format!(
+				"{:} bytes left after decoding attestation as {:?}",
+				input.len(),
+				self
+			)))
+		}
+
+		Ok(attestation)
+	}
+}
+
+/// Remember the attestation layout of the last seen runtime, the metadata is only fetched
+/// again when the spec version changes.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeDecoder {
+	current: Option<(RuntimeVersion, AttestationLayout)>,
+}
+
+impl RuntimeDecoder {
+	/// return the attestation layout of the runtime at block `at`
+	pub async fn layout(
+		&mut self,
+		client: &KiltClient,
+		at: Option<Hash>,
+	) -> Result<AttestationLayout, Error> {
+		let version = client.runtime_version(at).await?;
+		if let Some((ref current, layout)) = self.current {
+			if current == &version {
+				return Ok(layout)
+			}
+		}
+
+		// the metadata is only a sanity check, a runtime whose storage does not look like the
+		// layout of its spec version is not decoded at all
+		let layout = AttestationLayout::from_spec_version(version.spec_version);
+		let metadata = client.metadata(at).await?;
+		let metadata = RuntimeMetadataPrefixed::decode(&mut metadata.0.as_slice())?;
+		layout_from_metadata(&metadata)
+			.and_then(|found| {
+				if found == layout {
+					Ok(())
+				} else {
+					Err(format!(
+						"expected attestation layout {:?}, metadata has {:?}",
+						layout, found
+					))
+				}
+			})
+			.map_err(|e| {
+				Error::UnsupportedRuntime(format!(
+					"{:} spec version {:}: {:}",
+					version.spec_name, version.spec_version, e
+				))
+			})?;
+
+		log::info!(
+			target: KILT_LOG_TARGET,
+			"kilt runtime {:} spec version {:} stores attestations as {:?}",
+			version.spec_name,
+			version.spec_version,
+			layout
+		);
+		self.current = Some((version, layout));
+
+		Ok(layout)
+	}
+}
+
+fn layout_from_metadata(metadata: &RuntimeMetadataPrefixed) -> Result<AttestationLayout, String> {
+	let metadata = match &metadata.1 {
+		RuntimeMetadata::V14(m) => m,
+		_ => return Err("only metadata v14 is supported".to_owned()),
+	};
+
+	let entry = metadata
+		.pallets
+		.iter()
+		.find(|p| p.name == ATTESTATION_PALLET_PREFIX)
+		.and_then(|p| p.storage.as_ref())
+		.and_then(|s| s.entries.iter().find(|e| e.name == ATTESTATION_STORAGE_PREFIX))
+		.ok_or_else(|| {
+			format!("no storage {:}::{:}", ATTESTATION_PALLET_PREFIX, ATTESTATION_STORAGE_PREFIX)
+		})?;
+
+	let value_ty = match &entry.ty {
+		StorageEntryType::Map { value, .. } => value.id(),
+		StorageEntryType::Plain(_) => return Err("attestation storage is not a map".to_owned()),
+	};
+
+	let fields: Vec<&str> = match metadata.types.resolve(value_ty).map(|t| t.type_def()) {
+		Some(TypeDef::Composite(composite)) =>
+			composite.fields().iter().filter_map(|f| f.name().map(|n| n.as_str())).collect(),
+		_ => return Err("attestation value is not a struct".to_owned()),
+	};
+
+	KNOWN_LAYOUTS
+		.iter()
+		.find(|(names, _)| names == &fields.as_slice())
+		.map(|(_, layout)| *layout)
+		.ok_or_else(|| format!("unknown attestation layout {:?}", fields))
+}
+
+#[cfg(test)]
+mod tests {
+	use codec::Encode;
+
+	use super::*;
+
+	#[test]
+	fn layout_from_spec_version_should_work() {
+		assert_eq!(AttestationLayout::from_spec_version(10), AttestationLayout::DelegationId);
+		assert_eq!(
+			AttestationLayout::from_spec_version(23),
+			AttestationLayout::DelegationIdWithDeposit
+		);
+		assert_eq!(
+			AttestationLayout::from_spec_version(10600),
+			AttestationLayout::DelegationIdWithDeposit
+		);
+		assert_eq!(AttestationLayout::from_spec_version(10700), AttestationLayout::AuthorizationId);
+		assert_eq!(AttestationLayout::from_spec_version(11200), AttestationLayout::AuthorizationId);
+	}
+
+	#[test]
+	fn decode_legacy_layouts_should_work() {
+		let attester = AccountId::new([1u8; 32]);
+		let ctype_hash = Hash::repeat_byte(2);
+		let delegation_id = Hash::repeat_byte(3);
+
+		let raw = (ctype_hash, attester.clone(), Some(delegation_id), false).encode();
+		let attestation = AttestationLayout::DelegationId.decode(&raw).unwrap();
+		assert_eq!(attestation.attester, attester);
+		assert_eq!(attestation.authorization_id, Some(AuthorizationId::Delegation(delegation_id)));
+
+		let deposit = Deposit { owner: attester.clone(), amount: 10u128 };
+		let raw =
+			(ctype_hash, attester.clone(), Some(delegation_id), true, deposit.clone()).encode();
+		let attestation = AttestationLayout::DelegationIdWithDeposit.decode(&raw).unwrap();
+		assert!(attestation.revoked);
+		assert_eq!(attestation.deposit, deposit);
+
+		// the same bytes do not fit the latest layout
+		assert!(AttestationLayout::AuthorizationId.decode(&raw).is_err());
+	}
+}
@@ -1,13 +1,14 @@
 use std::time::Duration;
 
 use keeper_primitives::{
-	kilt::KILT_LOG_TARGET, ConfigInstance, Delay, Error, Hash, MqReceiver, MqSender, VerifyResult,
-	U64,
+	kilt::KILT_LOG_TARGET, monitor::MonitorMetrics, ConfigInstance, Delay, Error, Hash, MqReceiver,
+	MqSender, VerifyResult, U64,
 };
 
 pub async fn task_attestation(
 	config: &ConfigInstance,
 	msg_queue: (&mut MqSender, &mut MqReceiver),
+	state: &mut super::KiltState,
 ) -> Result<(), (Option<U64>, Error)> {
 	while let Ok(r) = msg_queue.1.recv_timeout(Delay::new(Duration::from_secs(1))).await {
 		// while let Ok(events) = event_receiver.recv().await {
@@ -34,7 +35,33 @@ pub async fn task_attestation(
 		log::info!(target: KILT_LOG_TARGET, "recv msg[{:}] in task3", hex::encode(batch_id));
 
 		// have handled resoluble error inside filter
-		let res = super::filter(&config.kilt_client, inputs.1).await.map_err(|e| (e.0, e.1))?;
+		let res = super::filter(&config.kilt_client, state, inputs.1)
+			.await
+			.map_err(|e| (e.0, e.1))?;
+
+		// report the credentials which do not match their attestation
+		if let Some(monitor) = &state.monitor {
+			for r in res.iter() {
+				if let Some(reason) = r.reject_reason.as_ref().filter(|r| r.is_mismatch()) {
+					let monitor_metrics = MonitorMetrics::new_with_msg(
+						KILT_LOG_TARGET.to_string(),
+						r.number,
+						format!(
+							"credential rejected|request_hash:{:}|data owner:{:}|attester:{:}|reason:{:}",
+							hex::encode(r.request_hash),
+							hex::encode(r.data_owner.0),
+							r.attester_identity
+								.as_ref()
+								.map(|i| i.did.clone())
+								.unwrap_or_else(|| hex::encode(r.attester)),
+							reason
+						),
+						config.name.clone(),
+					);
+					let _res = monitor.send(monitor_metrics).await;
+				}
+			}
+		}
 
 		if !res.is_empty() {
 			let res_str = serde_json::to_string(&(batch_id, res))
@@ -0,0 +1,345 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+use tokio::{
+	sync::RwLock,
+	time::{sleep, Duration},
+};
+use web3::types::H256;
+
+use crate::{audit, audit::Verdict, store};
+use keeper_primitives::{
+	monitor::MonitorSender,
+	moonbeam::{
+		self, utils::events, CanonicalEvent, VerifyingEvent, AGGREGATOR_CANONICAL_EVENT,
+		AGGREGATOR_VERIFYING_EVENT, MOONBEAM_AGGREGATOR_LOG_TARGET, MOONBEAM_BLOCK_DURATION,
+		MOONBEAM_SCAN_SPAN,
+	},
+	signer::Signer,
+	Address, Bytes32, ConfigInstance, Contract, Deserialize, Error, Http, Serialize, VerifyResult,
+	U64,
+};
+
+// blocks a finalized request is kept for after it became canonical
+const RETAIN_BLOCKS: u64 = 50_000;
+
+/// The verdict a keeper submitted for a request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+	pub worker: Address,
+	pub attester: Bytes32,
+	pub is_passed: bool,
+	pub block_number: Option<U64>,
+	// the submission, its calldata holds the full payload
+	pub tx_hash: Option<H256>,
+	// compared with our verdict already
+	#[serde(default)]
+	pub audited: bool,
+}
+
+/// The final verdict of a request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Canonical {
+	pub is_passed: bool,
+	pub block_number: Option<U64>,
+}
+
+/// Everything the aggregator emitted about one request
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestRecord {
+	pub request_hash: Bytes32,
+	pub data_owner: Address,
+	pub votes: Vec<Vote>,
+	pub canonical: Option<Canonical>,
+	// the verdict of this keeper
+	#[serde(default)]
+	pub own: Option<Verdict>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct KeptRequests {
+	// the next block to scan
+	next: Option<U64>,
+	requests: Vec<RequestRecord>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+	next: Option<U64>,
+	requests: HashMap<Bytes32, RequestRecord>,
+}
+
+impl TrackerState {
+	// record a vote sent by `tx_hash` in `block_number`, false if it was seen already
+	fn vote(
+		&mut self,
+		event: &VerifyingEvent,
+		block_number: Option<U64>,
+		tx_hash: Option<H256>,
+	) -> bool {
+		let record = self.record(event.request_hash, event.data_owner);
+		if record.votes.iter().any(|v| v.worker == event.worker) {
+			return false
+		}
+		record.votes.push(Vote {
+			worker: event.worker,
+			attester: event.attester,
+			is_passed: event.is_passed,
+			block_number,
+			tx_hash,
+			audited: false,
+		});
+		true
+	}
+
+	// record the final verdict, false if it was seen already
+	fn finalize(&mut self, event: &CanonicalEvent, block_number: Option<U64>) -> bool {
+		let record = self.record(event.request_hash, event.data_owner);
+		if record.canonical.is_some() {
+			return false
+		}
+		record.canonical = Some(Canonical { is_passed: event.is_passed, block_number });
+		true
+	}
+
+	fn record(&mut self, request_hash: Bytes32, data_owner: Address) -> &mut RequestRecord {
+		self.requests.entry(request_hash).or_insert_with(|| RequestRecord {
+			request_hash,
+			data_owner,
+			..Default::default()
+		})
+	}
+
+	// forget the requests finalized long before `best`
+	fn prune(&mut self, best: U64) {
+		self.requests
+			.retain(|_, r| match r.canonical.as_ref().and_then(|c| c.block_number) {
+				Some(number) => best.saturating_sub(number) < RETAIN_BLOCKS.into(),
+				None => true,
+			});
+	}
+}
+
+/// Tracks the votes of the keepers and the final verdict of every request, from the events of
+/// the aggregator. It is kept on disk with the next block to scan.
+pub struct RequestTracker {
+	path: PathBuf,
+	state: RwLock<TrackerState>,
+}
+
+impl RequestTracker {
+	pub fn load(path: &Path) -> Result<Self, moonbeam::Error> {
+		let kept: KeptRequests = store::read_json(path)?.unwrap_or_default();
+		let state = TrackerState {
+			next: kept.next,
+			requests: kept.requests.into_iter().map(|r| (r.request_hash, r)).collect(),
+		};
+		Ok(RequestTracker { path: path.to_owned(), state: RwLock::new(state) })
+	}
+
+	pub async fn get(&self, request_hash: &Bytes32) -> Option<RequestRecord> {
+		self.state.read().await.requests.get(request_hash).cloned()
+	}
+
+	// whether the verdict of the request is final
+	pub async fn is_canonical(&self, request_hash: &Bytes32) -> bool {
+		self.state
+			.read()
+			.await
+			.requests
+			.get(request_hash)
+			.map_or(false, |r| r.canonical.is_some())
+	}
+
+	// keep the verdict of this keeper on `v` once it is sent, to compare the ones of other
+	// keepers with
+	pub async fn record_own(&self, v: &VerifyResult) {
+		let mut state = self.state.write().await;
+		state.record(v.request_hash, v.data_owner).own = Some(v.into());
+	}
+
+	// compare our verdicts with the votes of other keepers which are not compared yet
+	async fn audit(
+		&self,
+		config: &ConfigInstance,
+		contract: &Contract<Http>,
+		monitor: &MonitorSender,
+	) -> Result<(), moonbeam::Error> {
+		let own_keys: Vec<Address> = std::iter::once(&config.signer)
+			.chain(&config.signer_pool)
+			.chain(&config.signer_optional)
+			.map(|s| s.address())
+			.collect();
+		let records: Vec<RequestRecord> = self
+			.state
+			.read()
+			.await
+			.requests
+			.values()
+			.filter(|r| r.own.is_some() && r.votes.iter().any(|v| !v.audited))
+			.cloned()
+			.collect();
+		if records.is_empty() {
+			return Ok(())
+		}
+
+		for record in &records {
+			let ours = record.own.as_ref().expect("filtered above; qed");
+			for vote in record.votes.iter().filter(|v| !v.audited) {
+				if !own_keys.contains(&vote.worker) {
+					audit::audit_vote(config, contract, monitor, record, ours, vote).await;
+				}
+			}
+		}
+
+		let mut state = self.state.write().await;
+		for record in &records {
+			if let Some(kept) = state.requests.get_mut(&record.request_hash) {
+				for vote in kept.votes.iter_mut() {
+					if record.votes.iter().any(|v| v.worker == vote.worker) {
+						vote.audited = true;
+					}
+				}
+			}
+		}
+		self.persist(&state)
+	}
+
+	async fn next_block(&self) -> Option<U64> {
+		self.state.read().await.next
+	}
+
+	fn persist(&self, state: &TrackerState) -> Result<(), moonbeam::Error> {
+		let kept =
+			KeptRequests { next: state.next, requests: state.requests.values().cloned().collect() };
+		store::write_json_atomic(&self.path, &kept)
+	}
+}
+
+// scan the aggregator events in [start, end] into `tracker`
+async fn scan_aggregator(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	tracker: &RequestTracker,
+	start: U64,
+	end: U64,
+) -> Result<(), moonbeam::Error> {
+	let eth = config.moonbeam_client.eth();
+	let verifying = events::<_, VerifyingEvent>(
+		eth.clone(),
+		contract,
+		AGGREGATOR_VERIFYING_EVENT,
+		Some(start),
+		Some(end),
+	)
+	.await?;
+	let canonical = events::<_, CanonicalEvent>(
+		eth,
+		contract,
+		AGGREGATOR_CANONICAL_EVENT,
+		Some(start),
+		Some(end),
+	)
+	.await?;
+
+	let mut state = tracker.state.write().await;
+	for (event, log) in &verifying {
+		if state.vote(event, log.block_number, log.transaction_hash) {
+			log::info!(
+				target: MOONBEAM_AGGREGATOR_LOG_TARGET,
+				"[vote] keeper {:?} voted {:} in block {:?}|request_hash: {:}|attester: {:}",
+				event.worker,
+				event.is_passed,
+				log.block_number,
+				hex::encode(event.request_hash),
+				hex::encode(event.attester)
+			);
+		}
+	}
+	for (event, log) in &canonical {
+		if state.finalize(event, log.block_number) {
+			log::info!(
+				target: MOONBEAM_AGGREGATOR_LOG_TARGET,
+				"[canonical] request finalized as {:} in block {:?}|request_hash: {:}|data owner: {:?}",
+				event.is_passed,
+				log.block_number,
+				hex::encode(event.request_hash),
+				event.data_owner
+			);
+		}
+	}
+	state.next = Some(end + 1);
+	state.prune(end);
+	tracker.persist(&state)
+}
+
+/// Follow the events of the aggregator from the kept block, else from `start`, and compare
+/// the votes of other keepers with ours
+pub async fn task_aggregator_scan(
+	config: &ConfigInstance,
+	tracker: &RequestTracker,
+	start: U64,
+	monitor: &MonitorSender,
+) -> Result<(), (Option<U64>, Error)> {
+	let span: U64 = MOONBEAM_SCAN_SPAN.into();
+	let mut start = tracker.next_block().await.unwrap_or(start);
+	loop {
+		let best = config.moonbeam_client.best_number().await.map_err(|e| (None, e.into()))?;
+		if start > best {
+			sleep(Duration::from_secs(MOONBEAM_BLOCK_DURATION)).await;
+			continue
+		}
+		let end = best.min(start + span);
+		// the registry may point to another aggregator since the last span
+		let contract = &config.aggregator_contract.get();
+		scan_aggregator(config, contract, tracker, start, end)
+			.await
+			.map_err(|e| (Some(start), e.into()))?;
+		tracker
+			.audit(config, contract, monitor)
+			.await
+			.map_err(|e| (Some(start), e.into()))?;
+		start = end + 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tracker_state_should_work() {
+		let mut state = TrackerState::default();
+		let request_hash = [1u8; 32];
+		let vote = VerifyingEvent {
+			data_owner: Address::repeat_byte(1),
+			request_hash,
+			worker: Address::repeat_byte(2),
+			attester: [3u8; 32],
+			is_passed: true,
+		};
+		assert!(state.vote(&vote, Some(10.into()), None));
+		// a rescanned block
+		assert!(!state.vote(&vote, Some(10.into()), None));
+		let other = VerifyingEvent { worker: Address::repeat_byte(4), ..vote.clone() };
+		assert!(state.vote(&other, Some(11.into()), None));
+
+		let canonical =
+			CanonicalEvent { data_owner: Address::repeat_byte(1), request_hash, is_passed: true };
+		assert!(state.finalize(&canonical, Some(12.into())));
+		assert!(!state.finalize(&canonical, Some(13.into())));
+
+		let record = &state.requests[&request_hash];
+		assert_eq!(record.votes.len(), 2);
+		assert_eq!(
+			record.canonical,
+			Some(Canonical { is_passed: true, block_number: Some(12.into()) })
+		);
+
+		state.prune((12 + RETAIN_BLOCKS - 1).into());
+		assert!(state.requests.contains_key(&request_hash));
+		state.prune((12 + RETAIN_BLOCKS).into());
+		assert!(state.requests.is_empty());
+	}
+}
@@ -0,0 +1,207 @@
+use web3::{
+	contract::tokens::Detokenize,
+	types::{TransactionId, H256},
+};
+
+use crate::{store, RequestRecord, Vote};
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	moonbeam::{Params, MOONBEAM_AUDIT_LOG_TARGET, SUBMIT_VERIFICATION},
+	Address, Bytes32, ConfigInstance, Contract, Deserialize, Http, Serialize, VerifyResult, U64,
+};
+
+/// The payload of a submission which matters for the verdict
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Verdict {
+	pub is_passed: bool,
+	pub attester: Bytes32,
+	// none if the submission could not be decoded
+	#[serde(default)]
+	pub calc_output: Option<Vec<u128>>,
+}
+
+impl From<&VerifyResult> for Verdict {
+	fn from(v: &VerifyResult) -> Self {
+		Verdict {
+			is_passed: v.is_passed,
+			attester: v.attester,
+			calc_output: Some(v.calc_output.clone()),
+		}
+	}
+}
+
+/// An entry of the audit log, our verdict differs from the one of another keeper, or a part of
+/// theirs could not be compared
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AuditEntry {
+	request_hash: String,
+	data_owner: Address,
+	worker: Address,
+	tx_hash: Option<H256>,
+	block_number: Option<U64>,
+	// the fields which differ
+	fields: Vec<String>,
+	// the fields which could not be compared
+	#[serde(default)]
+	not_auditable: Vec<String>,
+	ours: Verdict,
+	theirs: Verdict,
+}
+
+// the fields in which the verdicts differ, the calc output only if both are known
+fn mismatches(ours: &Verdict, theirs: &Verdict) -> Vec<String> {
+	let mut fields = vec![];
+	if ours.is_passed != theirs.is_passed {
+		fields.push("is_passed".to_owned());
+	}
+	if ours.attester != theirs.attester {
+		fields.push("attester".to_owned());
+	}
+	if let (Some(ours), Some(theirs)) = (&ours.calc_output, &theirs.calc_output) {
+		if ours != theirs {
+			fields.push("calc_output".to_owned());
+		}
+	}
+	fields
+}
+
+// the fields which can not be compared, the calc output of a submission forwarded by another
+// contract is unknown
+fn not_auditable(ours: &Verdict, theirs: &Verdict) -> Vec<String> {
+	let mut fields = vec![];
+	if ours.calc_output.is_none() || theirs.calc_output.is_none() {
+		fields.push("calc_output".to_owned());
+	}
+	fields
+}
+
+// the verdict of `vote`, with the calc output taken from its `submit` calldata if it is a
+// plain submission
+async fn their_verdict(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	request_hash: &Bytes32,
+	vote: &Vote,
+) -> Verdict {
+	let params = match vote.tx_hash {
+		Some(hash) => submitted_params(config, contract, hash).await,
+		None => None,
+	};
+	Verdict {
+		is_passed: vote.is_passed,
+		attester: vote.attester,
+		calc_output: params.filter(|p| &p.1 == request_hash).map(|p| p.6),
+	}
+}
+
+async fn submitted_params(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	hash: H256,
+) -> Option<Params> {
+	let tx = config
+		.moonbeam_client
+		.eth()
+		.transaction(TransactionId::Hash(hash))
+		.await
+		.map_err(|e| {
+			log::warn!(target: MOONBEAM_AUDIT_LOG_TARGET, "fail to fetch tx {:?}: {:?}", hash, e);
+			e
+		})
+		.ok()??;
+	let submit = contract.abi().function(SUBMIT_VERIFICATION).ok()?;
+	let input = &tx.input.0;
+	// forwarded by another contract
+	if input.len() < 4 || input[..4] != submit.short_signature() {
+		return None
+	}
+	let tokens = submit.decode_input(&input[4..]).ok()?;
+	Params::from_tokens(tokens).ok()
+}
+
+// compare `ours` with the vote of another keeper, a mismatch goes into the audit log and raises
+// an alert
+pub(crate) async fn audit_vote(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	monitor: &MonitorSender,
+	record: &RequestRecord,
+	ours: &Verdict,
+	vote: &Vote,
+) {
+	let theirs = their_verdict(config, contract, &record.request_hash, vote).await;
+	let fields = mismatches(ours, &theirs);
+	let not_auditable = not_auditable(ours, &theirs);
+	if fields.is_empty() && not_auditable.is_empty() {
+		return
+	}
+
+	let entry = AuditEntry {
+		request_hash: hex::encode(record.request_hash),
+		data_owner: record.data_owner,
+		worker: vote.worker,
+		tx_hash: vote.tx_hash,
+		block_number: vote.block_number,
+		fields,
+		not_auditable,
+		ours: ours.clone(),
+		theirs,
+	};
+	let payloads = serde_json::to_string(&entry).unwrap_or_default();
+	if let Err(e) = store::append_json_line(&config.channel_files.audit_log, &entry) {
+		log::error!(target: MOONBEAM_AUDIT_LOG_TARGET, "fail to write the audit log: {:?}", e);
+	}
+	// the comparable fields agree, which is still not taken as an agreement
+	if entry.fields.is_empty() {
+		log::warn!(
+			target: MOONBEAM_AUDIT_LOG_TARGET,
+			"[not auditable] {:?} of keeper {:?} not auditable|request_hash: {:}|{}",
+			entry.not_auditable,
+			vote.worker,
+			entry.request_hash,
+			payloads
+		);
+		return
+	}
+
+	log::warn!(
+		target: MOONBEAM_AUDIT_LOG_TARGET,
+		"[mismatch] keeper {:?} disagrees on {:?}|request_hash: {:}|{}",
+		vote.worker,
+		entry.fields,
+		entry.request_hash,
+		payloads
+	);
+
+	let monitor_metrics = MonitorMetrics::new_with_msg(
+		MOONBEAM_AUDIT_LOG_TARGET.to_string(),
+		vote.block_number,
+		format!("[high priority] verdict mismatch|{}", payloads),
+		config.name.clone(),
+	);
+	let _res = monitor.send(monitor_metrics).await;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mismatches_should_work() {
+		let ours = Verdict { is_passed: true, attester: [1u8; 32], calc_output: Some(vec![1, 2]) };
+		assert!(mismatches(&ours, &ours).is_empty());
+
+		// the calc output is left out if it is unknown
+		let theirs = Verdict { is_passed: false, calc_output: None, ..ours.clone() };
+		assert_eq!(mismatches(&ours, &theirs), vec!["is_passed".to_owned()]);
+		// but reported as not auditable
+		assert!(not_auditable(&ours, &ours).is_empty());
+		assert_eq!(not_auditable(&ours, &theirs), vec!["calc_output".to_owned()]);
+
+		let theirs = Verdict { attester: [2u8; 32], calc_output: Some(vec![1, 3]), ..ours.clone() };
+		assert_eq!(
+			mismatches(&ours, &theirs),
+			vec!["attester".to_owned(), "calc_output".to_owned()]
+		);
+	}
+}
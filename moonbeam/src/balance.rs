@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use tokio::{
+	sync::RwLock,
+	time::{sleep, Duration},
+};
+use web3::types::U256;
+
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	moonbeam::{BalanceLevel, MOONBEAM_BALANCE_LOG_TARGET},
+	signer::{Signer, SignerRef},
+	Address, ConfigInstance,
+};
+
+/// The balance levels of the signing keys, kept up to date by the balance task. A key is
+/// taken as healthy until its balance is known.
+#[derive(Default)]
+pub struct Balances(RwLock<HashMap<Address, BalanceLevel>>);
+
+impl Balances {
+	pub async fn level(&self, address: Address) -> BalanceLevel {
+		self.0.read().await.get(&address).cloned().unwrap_or(BalanceLevel::Healthy)
+	}
+
+	// too poor to send anything with
+	pub async fn is_depleted(&self, address: Address) -> bool {
+		self.level(address).await == BalanceLevel::Depleted
+	}
+
+	pub async fn all_depleted(&self, signers: &[SignerRef]) -> bool {
+		for signer in signers {
+			if !self.is_depleted(signer.address()).await {
+				return false
+			}
+		}
+		true
+	}
+
+	// update the level of every signing key, a key whose level gets worse is reported
+	async fn check(&self, config: &ConfigInstance, monitor: &MonitorSender) {
+		let mut signers: Vec<&SignerRef> = vec![];
+		for signer in std::iter::once(&config.signer)
+			.chain(&config.signer_pool)
+			.chain(&config.signer_optional)
+		{
+			if !signers.iter().any(|s| s.address() == signer.address()) {
+				signers.push(signer);
+			}
+		}
+
+		let balance_config = &config.moonbeam_config.balance;
+		for signer in signers {
+			let address = signer.address();
+			// keep the last level if the balance can not be fetched
+			let balance = match config.moonbeam_client.eth().balance(address, None).await {
+				Ok(balance) => balance,
+				Err(e) => {
+					log::warn!(
+						target: MOONBEAM_BALANCE_LOG_TARGET,
+						"fail to fetch the balance of {:?}: {:?}",
+						address,
+						e
+					);
+					continue
+				},
+			};
+			let level = balance_config.level(balance);
+			let last = self.0.write().await.insert(address, level).unwrap_or(BalanceLevel::Healthy);
+
+			log::info!(
+				target: MOONBEAM_BALANCE_LOG_TARGET,
+				"balance of {:?}: {:} wei|level: {:?}",
+				address,
+				balance,
+				level
+			);
+			if level > last {
+				report(config, monitor, address, balance, level).await;
+			} else if level < last {
+				log::info!(
+					target: MOONBEAM_BALANCE_LOG_TARGET,
+					"balance of {:?} back to {:?} from {:?}",
+					address,
+					level,
+					last
+				);
+			}
+		}
+	}
+}
+
+async fn report(
+	config: &ConfigInstance,
+	monitor: &MonitorSender,
+	address: Address,
+	balance: U256,
+	level: BalanceLevel,
+) {
+	let msg = match level {
+		BalanceLevel::Healthy => return,
+		BalanceLevel::Low => "[warning] balance is low",
+		BalanceLevel::Critical => "[alert] balance is critical",
+		BalanceLevel::Depleted => "[alert] balance is below the floor, submissions are held",
+	};
+	log::warn!(
+		target: MOONBEAM_BALANCE_LOG_TARGET,
+		"{}|key: {:?}|balance: {:} wei",
+		msg,
+		address,
+		balance
+	);
+	let monitor_metrics = MonitorMetrics::new_with_msg(
+		MOONBEAM_BALANCE_LOG_TARGET.to_string(),
+		None,
+		format!("{}|key:{:?}|balance:{:} wei", msg, address, balance),
+		config.name.clone(),
+	);
+	let _res = monitor.send(monitor_metrics).await;
+}
+
+/// Check the balances of the signing keys every `interval_secs`
+pub async fn task_balance(config: &ConfigInstance, balances: &Balances, monitor: &MonitorSender) {
+	let interval = Duration::from_secs(config.moonbeam_config.balance.interval_secs.max(1));
+	loop {
+		balances.check(config, monitor).await;
+		sleep(interval).await;
+	}
+}
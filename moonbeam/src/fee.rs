@@ -0,0 +1,59 @@
+use web3::types::{BlockNumber, U256};
+
+use keeper_primitives::{
+	moonbeam::{self, Fees, MOONBEAM_SUBMIT_LOG_TARGET},
+	ConfigInstance,
+};
+
+// fees for a new tx. Type-2 fees come from `eth_feeHistory`: the priority fee is the median
+// reward of the configured percentile, the max fee leaves room for the base fee to double.
+// Chains without a base fee, or without `eth_feeHistory`, get a legacy gas price.
+pub async fn suggest_fees(config: &ConfigInstance) -> Result<Fees, moonbeam::Error> {
+	let fee_config = &config.moonbeam_config.fee;
+	if fee_config.eip1559 {
+		let history = config
+			.moonbeam_client
+			.eth()
+			.fee_history(
+				fee_config.fee_history_blocks.into(),
+				BlockNumber::Latest,
+				Some(vec![fee_config.priority_fee_percentile as f64]),
+			)
+			.await;
+
+		match history {
+			Ok(history) => {
+				// the last one is the base fee of the next block
+				let base_fee = history.base_fee_per_gas.last().cloned().unwrap_or_default();
+				if !base_fee.is_zero() {
+					let mut rewards: Vec<U256> = history
+						.reward
+						.unwrap_or_default()
+						.iter()
+						.filter_map(|r| r.first().cloned())
+						.collect();
+					rewards.sort();
+					let max_priority_fee_per_gas =
+						rewards.get(rewards.len() / 2).cloned().unwrap_or_default();
+					let fees = Fees::Eip1559 {
+						max_fee_per_gas: base_fee * 2 + max_priority_fee_per_gas,
+						max_priority_fee_per_gas,
+					};
+					return Ok(fees.capped(fee_config.bump.cap()))
+				}
+				log::info!(
+					target: MOONBEAM_SUBMIT_LOG_TARGET,
+					"[fee] the chain has no base fee, use legacy transactions"
+				);
+			},
+			Err(e) => log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"[fee] fail to fetch fee history, use legacy transactions. err: {:?}",
+				e
+			),
+		}
+	}
+
+	let gas_price = config.moonbeam_client.eth().gas_price().await?;
+	Ok(Fees::Legacy { gas_price }.capped(fee_config.bump.cap()))
+}
@@ -1,24 +1,46 @@
 #![feature(async_closure)]
 
 use rand::{Rng, SeedableRng};
-use secp256k1::SecretKey;
 use web3::{
-	signing::{Key, SecretKeyRef},
-	types::{TransactionId, H256, U256},
+	contract::tokens::Tokenize,
+	types::{TransactionParameters, H256, U256},
 };
 
-use crate::task::RetryQueue;
+use crate::{receipt::TxStatus, task::RetryQueue};
+pub use aggregator::{task_aggregator_scan, Canonical, RequestRecord, RequestTracker, Vote};
+pub use audit::Verdict;
+pub use balance::{task_balance, Balances};
 use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
 	moonbeam::{
-		self, Events, Params, ProofEvent, IS_FINISHED, MAX_RETRY_TIMES, MOONBEAM_LISTENED_EVENT,
+		self, Events, Params, ProofEvent, IS_FINISHED, MOONBEAM_LISTENED_EVENT,
 		MOONBEAM_RESUBMIT_LOG_TARGET, MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SCAN_SPAN,
 		MOONBEAM_SUBMIT_LOG_TARGET, SUBMIT_STATUS_QUERY, SUBMIT_VERIFICATION,
 	},
+	signer::{Signer, SignerRef},
 	Address, ConfigInstance, Contract, Hash, Http, MoonbeamClient, Result as KeeperResult,
 	VerifyResult, Web3Options, U64,
 };
-pub use task::{create_retry_queue, task_resubmit, task_scan, task_submit, FatTx};
+pub use nonce::{NonceManager, NonceManagers};
+pub use registry::task_registry;
+pub use scheduler::Scheduler;
+pub use stuck::cancel_tx;
+pub use task::{
+	create_retry_queue, load_retry_queue, task_resubmit, task_scan, task_submit, FatTx,
+};
 
+mod aggregator;
+mod audit;
+mod balance;
+mod fee;
+mod nonce;
+mod receipt;
+mod registry;
+mod revert;
+mod scheduler;
+mod simulate;
+mod store;
+mod stuck;
 mod task;
 
 // scan moonbeam events
@@ -130,38 +152,57 @@ type TxHashAndInfo = (Option<H256>, VerifyResult);
 pub async fn submit_txs(
 	config: &ConfigInstance,
 	contract: &Contract<Http>,
-	keeper_pri: SecretKey,
+	scheduler: &Scheduler,
+	nonces: &NonceManagers,
+	balances: &Balances,
+	tracker: &RequestTracker,
 	inputs: Vec<VerifyResult>,
 	last_sent_tx: &mut FatTx,
+	monitor: &MonitorSender,
 ) -> Result<Vec<FatTx>, (Option<U64>, moonbeam::Error)> {
-	let key_ref = SecretKeyRef::new(&keeper_pri);
-	let keeper_address = key_ref.address();
-
 	// init a vec to hold info to pass to next task
 	let mut result_for_next_task = vec![];
 
 	for v in inputs {
+		// the aggregator can not take the outcome of rejected credentials
+		if v.reject_reason.is_some() && config.moonbeam_config.skip_rejected {
+			log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"skip submitting rejected credential|request_hash: {:}|reason: {:?}",
+				hex::encode(v.request_hash),
+				v.reject_reason.as_ref().map(|r| r.to_string())
+			);
+			continue
+		}
+
 		// TODO: read multiple times?
 		// todo:throw error in production network
 		// if unable to get `has_submitted` result, then use false
-		let has_submitted: bool = contract
-			.query(
-				SUBMIT_STATUS_QUERY,
-				(keeper_address, v.data_owner, v.request_hash),
-				None,
-				Web3Options::default(),
-				None,
-			)
-			.await
-			.map_err(|e| {
-				log::error!(
-					target: MOONBEAM_SUBMIT_LOG_TARGET,
-					"has_submiited query error: {:?}",
-					&e
-				);
-				e
-			})
-			.unwrap_or_default();
+		// any submit key may have submitted it before a restart
+		let mut has_submitted = false;
+		for signer in scheduler.signers() {
+			has_submitted = contract
+				.query(
+					SUBMIT_STATUS_QUERY,
+					(signer.address(), v.data_owner, v.request_hash),
+					None,
+					Web3Options::default(),
+					None,
+				)
+				.await
+				.map_err(|e| {
+					log::error!(
+						target: MOONBEAM_SUBMIT_LOG_TARGET,
+						"has_submiited query error: {:?}",
+						&e
+					);
+					e
+				})
+				.unwrap_or_default();
+			if has_submitted {
+				break
+			}
+		}
 
 		// if unable to get `is_finished` result, then use false
 		let is_finished: bool = contract
@@ -192,13 +233,20 @@ pub async fn submit_txs(
 			continue
 		}
 
+		// the key to submit it with
+		let signer = scheduler.pick(&v, nonces, balances).await;
+		let keeper_address = signer.address();
+		let key_nonces = nonces.get(keeper_address).map_err(|e| (None, e))?;
+
 		log::info!(
 			target: MOONBEAM_SUBMIT_LOG_TARGET,
-			"Start submitting: tx which contains user address: {:} |request_hash: {:}| root hash : {:} | isPassed: {}",
+			"Start submitting: tx which contains user address: {:} |request_hash: {:}| root hash : {:} | isPassed: {} | rejected: {:?} | key: {:?}",
 			v.data_owner,
 			hex::encode(v.request_hash),
 			hex::encode(v.root_hash),
-			v.is_passed
+			v.is_passed,
+			v.reject_reason.as_ref().map(|r| r.to_string()),
+			keeper_address
 		);
 
 		// construct parameters for the contract call.
@@ -206,17 +254,41 @@ pub async fn submit_txs(
 		// todo: update
 		let params = v1.get_submit_params();
 
-		// pick a nonce, construct raw tx and send it onchain
-		// todo: throw?
-		let nonce = latest_nonce(
-			Some(last_sent_tx.clone()),
-			config,
-			keeper_address,
-			MOONBEAM_SUBMIT_LOG_TARGET,
-		)
-		.await
-		.ok();
-		let options = Web3Options::with(|options| options.nonce = nonce);
+		let gas = estimate_gas(config, contract, keeper_address, params.clone()).await;
+
+		// only broadcast submissions which would succeed
+		if let Err(revert) =
+			simulate::simulate_submit(config, contract, keeper_address, &v, params.clone(), gas)
+				.await
+		{
+			let alert = revert.kind.should_alert();
+			simulate::report(config, monitor, MOONBEAM_SUBMIT_LOG_TARGET, &v, &revert, alert).await;
+			if revert.kind.should_retry() {
+				// leave it to the resubmit task, without a tx hash it is tried again right away
+				let send_at = config
+					.moonbeam_client
+					.eth()
+					.block_number()
+					.await
+					.map_err(|e| (None, e.into()))?;
+				result_for_next_task.push(FatTx {
+					send_at,
+					gas: Some(gas),
+					tx: (None, v.clone()),
+					..Default::default()
+				});
+			}
+			continue
+		}
+
+		// leave the fees to the node if they can not be fetched
+		let fees = fee::suggest_fees(config)
+			.await
+			.map_err(|e| {
+				log::warn!(target: MOONBEAM_SUBMIT_LOG_TARGET, "[fee] fail to suggest fees: {:?}", e);
+				e
+			})
+			.ok();
 		// MUST get a value, otherwise throw it out
 		let send_at = config
 			.moonbeam_client
@@ -224,7 +296,25 @@ pub async fn submit_txs(
 			.block_number()
 			.await
 			.map_err(|e| (None, e.into()))?;
-		let tx_hash = construct_tx_and_send(contract, keeper_pri, options.clone(), params).await;
+		// pick a nonce, construct raw tx and send it onchain
+		// leave it to the node if the nonce manager fails
+		let nonce = key_nonces
+			.next(config)
+			.await
+			.map_err(|e| {
+				log::error!(target: MOONBEAM_SUBMIT_LOG_TARGET, "[nonce] fail to reserve: {:?}", e);
+				e
+			})
+			.ok();
+		let options = Web3Options::with(|options| {
+			options.nonce = nonce;
+			options.gas = Some(gas);
+			if let Some(fees) = fees {
+				fees.apply(options);
+			}
+		});
+		let tx_hash =
+			construct_tx_and_send(config, contract, signer.as_ref(), options.clone(), params).await;
 		// Ok(hash) -> Some(hash)
 		// Err(_) => None and log error
 		let tx_hash = match tx_hash {
@@ -238,6 +328,8 @@ pub async fn submit_txs(
 					v.is_passed,
 					hex::encode(v.attester),
 				);
+				// to compare the verdicts of other keepers with
+				tracker.record_own(&v).await;
 				Some(hash)
 			},
 			Err(_) => {
@@ -249,13 +341,22 @@ pub async fn submit_txs(
 					v.is_passed,
 					hex::encode(v.attester),
 				);
+				if let Some(nonce) = nonce {
+					key_nonces.release(config, nonce).await;
+				}
 				None
 			},
 		};
 
 		let tx_info = (tx_hash, v.clone());
-		// todo: record gas price?
-		*last_sent_tx = FatTx { send_at, nonce, tx: tx_info.clone(), ..Default::default() };
+		*last_sent_tx = FatTx {
+			send_at,
+			nonce,
+			gas: Some(gas),
+			fees,
+			from: Some(keeper_address),
+			tx: tx_info.clone(),
+		};
 
 		result_for_next_task.push(last_sent_tx.clone());
 	}
@@ -268,206 +369,357 @@ pub async fn submit_txs(
 // and push the latest submitted tx from the back
 // will throw error if:
 // - updating nonce fails
-// - updating gas price fails
+// - updating fees fails
 pub async fn resubmit_txs(
 	config: &ConfigInstance,
 	contract: &Contract<Http>,
-	keeper_pri_optional: Option<SecretKey>,
+	signer_optional: Option<&SignerRef>,
+	nonces: &NonceManagers,
+	balances: &Balances,
+	tracker: &RequestTracker,
 	queue: RetryQueue,
+	monitor: &MonitorSender,
 ) -> Result<(), (Option<U64>, moonbeam::Error)> {
 	// if optional key is not set
 	// just return to commit the msg in the channel
-	let keeper_sec_key = match keeper_pri_optional {
-		Some(sec_key) => sec_key,
+	let signer = match signer_optional {
+		Some(signer) => signer.as_ref(),
 		None => return Ok(()),
 	};
 
-	let key_ref = SecretKeyRef::new(&keeper_sec_key);
-	let keeper_address = key_ref.address();
+	let keeper_address = signer.address();
+	// keep the queue as it is until the key is funded again
+	if balances.is_depleted(keeper_address).await {
+		log::warn!(
+			target: MOONBEAM_RESUBMIT_LOG_TARGET,
+			"[balance] {:?} is below the floor, hold {:} resubmissions",
+			keeper_address,
+			queue.lock().await.len()
+		);
+		return Ok(())
+	}
+	let nonces = nonces.get(keeper_address).map_err(|e| (None, e))?;
 
 	let mut queue_guard = queue.lock().await;
 
 	// check the tx hash from the front
-	while let Some(mut item) = queue_guard.pop_front() {
-		let is_included = match item.tx_info().0 {
-			Some(hash) => {
-				let maybe_tx_hash =
-					config.moonbeam_client.eth().transaction(TransactionId::Hash(hash)).await;
-
-				// if tx hash is included
-				if let Ok(Some(tx)) = maybe_tx_hash {
-					log::info!(
-						target: MOONBEAM_RESUBMIT_LOG_TARGET,
-						"[Already included] tx_hash [{:}] included in blockNumber: #{:?} which contains user address: {:} |request_hash: {:?}| root hash : {:} | isPassed: {}",
-						tx.hash,
-						tx.block_number,
-						&item.tx_info().1.data_owner,
-						hex::encode(&item.tx_info().1.request_hash),
-						hex::encode(item.tx_info().1.root_hash),
-						item.tx_info().1.is_passed
-					);
-					true
-				} else {
-					// not included yet or encounter error
-					item.retry_times += 1;
-					false
+	// keep the queue after every decision
+	loop {
+		task::persist_retry_queue(&config.channel_files.retry_queue, &queue_guard);
+		let mut item = match queue_guard.pop_front() {
+			Some(item) => item,
+			None => break,
+		};
+		let bump = &config.moonbeam_config.fee.bump;
+		let v = item.tx_info().1;
+
+		// the aggregator has a final verdict, nothing more to submit. A tx of it still pending
+		// holds its nonce though, wait for it to be mined, or cancel it once it is stuck
+		if tracker.is_canonical(&v.request_hash).await {
+			if item.tx_info().0.is_some() && item.fat_tx.nonce.is_some() {
+				let held = match fee::suggest_fees(config).await {
+					Ok(suggested) => {
+						let fees = match item.fat_tx.fees {
+							Some(last) => last.bumped(suggested, bump),
+							None => suggested,
+						};
+						stuck::resubmit_nonce(config, signer, nonces, &item, fees, monitor).await
+					},
+					Err(e) => Err(e),
+				};
+				match held {
+					Ok(None) => {},
+					Ok(Some(nonce)) => {
+						log::info!(
+							target: MOONBEAM_RESUBMIT_LOG_TARGET,
+							"[canonical] wait for tx [{:?}] at nonce {:} to be mined|request_hash: {:}",
+							item.tx_info().0,
+							nonce,
+							hex::encode(&v.request_hash)
+						);
+						queue_guard.push_front(item);
+						break
+					},
+					Err(e) => {
+						log::error!(
+							target: MOONBEAM_RESUBMIT_LOG_TARGET,
+							"[stuck] fail to check the nonce of {:?}: {:?}",
+							item.tx_info().0,
+							e
+						);
+						queue_guard.push_front(item);
+						break
+					},
 				}
-			},
-
-			None => {
-				// no tx hash provided from last task
-				false
-			},
+			}
+			log::info!(
+				target: MOONBEAM_RESUBMIT_LOG_TARGET,
+				"[canonical] drop tx [{:?}], the request is finalized|request_hash: {:}",
+				item.tx_info().0,
+				hex::encode(&v.request_hash)
+			);
+			continue
+		}
+		let status = match item.tx_info().0 {
+			Some(hash) =>
+				receipt::tx_status(config, contract, hash, item.fat_tx.sender(config), &v).await,
+			// no tx hash provided from last task
+			None => TxStatus::Pending,
 		};
 
-		// if included, consume this item and continue to check next one
-		if is_included {
-			continue
+		match status {
+			// if succeeded, consume this item and continue to check next one
+			TxStatus::Succeeded(block_number) => {
+				log::info!(
+					target: MOONBEAM_RESUBMIT_LOG_TARGET,
+					"[Already included] tx_hash [{:?}] included in blockNumber: #{:} which contains user address: {:} |request_hash: {:?}| root hash : {:} | isPassed: {}",
+					item.tx_info().0,
+					block_number,
+					&v.data_owner,
+					hex::encode(&v.request_hash),
+					hex::encode(v.root_hash),
+					v.is_passed
+				);
+				continue
+			},
+			// wait for the confirmations without resubmitting
+			TxStatus::Unconfirmed => {
+				queue_guard.push_front(item);
+				break
+			},
+			TxStatus::Pending => {
+				// not included yet or encounter error
+				if item.tx_info().0.is_some() {
+					item.retry_times = item.retry_times.saturating_add(1);
+				}
+			},
+			TxStatus::Reverted { block_number, reason, kind } => {
+				log::warn!(
+					target: MOONBEAM_RESUBMIT_LOG_TARGET,
+					"[reverted] tx_hash [{:?}] in blockNumber: #{:}|request_hash: {:}|reason: {:?}|kind: {:?}",
+					item.tx_info().0,
+					block_number,
+					hex::encode(&v.request_hash),
+					reason,
+					kind
+				);
+				item.reverts += 1;
+				let give_up = kind.should_retry() && item.reverts > bump.max_attempts;
+				if kind.should_alert() || give_up {
+					let monitor_metrics = MonitorMetrics::new_with_msg(
+						MOONBEAM_RESUBMIT_LOG_TARGET.to_string(),
+						Some(block_number),
+						format!(
+							"submission reverted|request_hash:{:}|data owner:{:}|reason:{:?}|kind:{:?}|reverts:{:}",
+							hex::encode(v.request_hash),
+							hex::encode(v.data_owner.0),
+							reason,
+							kind,
+							item.reverts
+						),
+						config.name.clone(),
+					);
+					let _res = monitor.send(monitor_metrics).await;
+				}
+				// drop it unless another submission may succeed
+				if !kind.should_retry() || give_up {
+					continue
+				}
+				// the reverted tx is final, resubmit right away
+				item.fat_tx.tx.0 = None;
+			},
 		}
 
 		// if tx not included and retry times <= max, push back the item
 		// to the queue and break
-		if item.retry_times <= MAX_RETRY_TIMES {
+		if item.tx_info().0.is_some() && item.retry_times <= bump.max_attempts {
 			queue_guard.push_front(item);
 			break
 		}
 
-		// if the tx has been retried enough times(max_retry_times) still not included
+		// if the tx has been retried enough times(max_attempts) still not included
 		// or tx hash is not passed from the last task, re-construct and submit
-		let (new_nonce, new_price) = {
-			let last_sent_tx = queue_guard.back().map(|f| f.fat_tx.clone());
-			// update nonce, will throw error
-			let nonce =
-				latest_nonce(last_sent_tx, &config, keeper_address, MOONBEAM_RESUBMIT_LOG_TARGET)
-					.await
-					.map_err(|e| (None, e.into()))?;
-
-			// update gas
-			// suggest gas price fetch, if error throw
-			// throw error if gas_price fetching fails
-			let suggested_gas_price =
-				config.moonbeam_client.eth().gas_price().await.map_err(|e| (None, e.into()))?;
-			let gas_price = {
-				// todo: make 1.1 configurable!!
-				let new_price = item.fat_tx.gas_price * 110 / 100;
-				let new_price =
-					if new_price > suggested_gas_price { new_price } else { suggested_gas_price };
-				new_price
-			};
-
-			(nonce, gas_price)
+		// update fees
+		// throw error if fetching the suggested fees fails
+		let new_fees = {
+			let suggested = fee::suggest_fees(config).await.map_err(|e| (None, e))?;
+			match item.fat_tx.fees {
+				Some(last) => last.bumped(suggested, bump),
+				None => suggested,
+			}
 		};
 
-		let resubmit_strategy = Web3Options::with(|options| {
-			options.nonce = Some(new_nonce);
-			options.gas_price = Some(new_price);
-		});
-
 		let params = item.tx_info().1.get_submit_params();
+		// reuse the limit picked by the submit task
+		let gas = match item.fat_tx.gas {
+			Some(gas) => gas,
+			None => estimate_gas(config, contract, keeper_address, params.clone()).await,
+		};
+		item.fat_tx.gas = Some(gas);
+
+		if let Err(revert) =
+			simulate::simulate_submit(config, contract, keeper_address, &v, params.clone(), gas)
+				.await
+		{
+			item.reverts += 1;
+			let give_up = revert.kind.should_retry() && item.reverts > bump.max_attempts;
+			let alert = revert.kind.should_alert() || give_up;
+			simulate::report(config, monitor, MOONBEAM_RESUBMIT_LOG_TARGET, &v, &revert, alert)
+				.await;
+			// try it again later, unless it would never succeed
+			if revert.kind.should_retry() && !give_up {
+				queue_guard.push_front(item);
+				break
+			}
+			continue
+		}
+
 		let send_at = config
 			.moonbeam_client
 			.eth()
 			.block_number()
 			.await
 			.map_err(|e| (None, e.into()))?;
+		// replace the tx at its nonce, unless it is stuck there
+		let held_nonce =
+			match stuck::resubmit_nonce(config, signer, nonces, &item, new_fees, monitor).await {
+				Ok(nonce) => nonce,
+				Err(e) => {
+					log::error!(
+						target: MOONBEAM_RESUBMIT_LOG_TARGET,
+						"[stuck] fail to check the nonce of {:?}: {:?}",
+						item.tx_info().0,
+						e
+					);
+					queue_guard.push_front(item);
+					break
+				},
+			};
+		// update nonce, will throw error
+		let new_nonce = match held_nonce {
+			Some(nonce) => nonce,
+			None => nonces.next(config).await.map_err(|e| (None, e))?,
+		};
+
+		let resubmit_strategy = Web3Options::with(|options| {
+			options.nonce = Some(new_nonce);
+			options.gas = Some(gas);
+			new_fees.apply(options);
+		});
 		let tx_hash =
-			construct_tx_and_send(contract, keeper_sec_key, resubmit_strategy, params).await;
+			construct_tx_and_send(config, contract, signer, resubmit_strategy, params).await;
 		// update the queue
 		match tx_hash {
 			Ok(hash) => {
 				log::info!(
 					target: MOONBEAM_RESUBMIT_LOG_TARGET,
-					"[re submitted]|tx:{:}|data owner:{:}|root_hash:{:}|is_passed: {:}|attester: {:}",
+					"[re submitted]|tx:{:}|data owner:{:}|root_hash:{:}|is_passed: {:}|attester: {:}|fees: {:?}",
 					hash,
 					item.tx_info().1.data_owner,
 					hex::encode(item.tx_info().1.root_hash),
 					item.tx_info().1.is_passed,
 					hex::encode(item.tx_info().1.attester),
+					new_fees
 				);
+				tracker.record_own(&item.tx_info().1).await;
 				// update item
+				item.update_after_resubmit(
+					keeper_address,
+					new_nonce,
+					new_fees,
+					send_at,
+					Some(hash),
+				);
 
 				// succeed to send, push it from the back to update the latest nonce
-				queue_guard.push_back(item.clone());
+				queue_guard.push_back(item);
 			},
 
 			Err(_) => {
-				// fail to send, put it back from the front
-				queue_guard.push_front(item.clone());
+				// fail to send, put it back from the front and try again next time
+				if held_nonce.is_none() {
+					nonces.release(config, new_nonce).await;
+				}
+				queue_guard.push_front(item);
+				break
 			},
 		}
-
-		item.update_after_resubmit(new_nonce, new_price, send_at, tx_hash.ok());
 	}
+	task::persist_retry_queue(&config.channel_files.retry_queue, &queue_guard);
 
 	Ok(())
 }
 
-// pick a nonce to construct tx and send
-// succeed if it returns Ok(tx_hash)
-// if fail to get nonce, will throw error
-pub async fn construct_tx_and_send(
+// gas limit of the submission, estimated by the node with the exact `params` plus the configured
+// margin, or the configured default if the estimation fails
+pub async fn estimate_gas(
+	config: &ConfigInstance,
 	contract: &Contract<Http>,
-	keeper_pri: SecretKey,
-	// including updated nonce
-	mut options: Web3Options,
+	from: Address,
 	params: Params,
-) -> Result<H256, moonbeam::Error> {
-	// construct the send option, the must important thing is nonce.
-	options.gas = Some(1000000_u128.into());
-
-	// todo: adjust gas price?
-	// send tx for this contract call, and return tx_hash immediately.
-	// todo: handle the send tx error
-	contract
-		.signed_call(SUBMIT_VERIFICATION, params, options, &keeper_pri)
+) -> U256 {
+	let gas_config = &config.moonbeam_config.gas;
+	let estimated = contract
+		.estimate_gas(SUBMIT_VERIFICATION, params, from, Web3Options::default())
 		.await
 		.map_err(|e| {
-			log::error!(
+			log::warn!(
 				target: MOONBEAM_SUBMIT_LOG_TARGET,
-				"[submit error] fail to submit: {:?}",
+				"fail to estimate gas, use the default limit {:}. err: {:?}",
+				gas_config.default,
 				e
 			);
-			e.into()
+			e
 		})
+		.ok();
+	let limit = gas_config.limit(estimated);
+	log::info!(
+		target: MOONBEAM_SUBMIT_LOG_TARGET,
+		"[gas] estimated: {:?} | limit: {:}",
+		estimated,
+		limit
+	);
+	limit
 }
 
-// will throw error if failed to get nonce
-pub async fn latest_nonce(
-	last_sent_tx: Option<FatTx>,
+// send the submission with the given options
+// succeed if it returns Ok(tx_hash)
+pub async fn construct_tx_and_send(
 	config: &ConfigInstance,
-	keeper_address: Address,
-	log_target: &str,
-) -> Result<U256, moonbeam::Error> {
-	// best == last.send_at && nonce has value
-	let nonce = match last_sent_tx {
-		None => config
-			.moonbeam_client
-			.eth()
-			.transaction_count(keeper_address, None)
-			.await
-			.map_err(|e| moonbeam::Error::Web3Error(e))?,
-		Some(fat_tx) => {
-			// pick last item in the queue, for the last item will hold the newest nonce.
-			let best = config.moonbeam_client.best_number().await.map_err(|e| {
-				log::error!(target: log_target, "[nonce] fail to get best, err is {:?}", e);
-				e
-			})?;
-
-			// todo: use functional prpgraming way to rewrite
-			if best == fat_tx.send_at && fat_tx.nonce.is_some() {
-				fat_tx.nonce.unwrap() + U256::one()
-			} else {
-				// 1. best != last.send_at
-				// 2. last.nonce is none
-				config
-					.moonbeam_client
-					.eth()
-					.transaction_count(keeper_address, None)
-					.await
-					.map_err(|e| moonbeam::Error::Web3Error(e))?
-			}
-		},
+	contract: &Contract<Http>,
+	signer: &dyn Signer,
+	// including updated nonce and gas limit
+	options: Web3Options,
+	params: Params,
+) -> Result<H256, moonbeam::Error> {
+	let data = contract
+		.abi()
+		.function(SUBMIT_VERIFICATION)?
+		.encode_input(&params.into_tokens())?;
+	let tx = TransactionParameters {
+		nonce: options.nonce,
+		to: Some(contract.address()),
+		gas: options.gas.unwrap_or_else(|| config.moonbeam_config.gas.default.into()),
+		gas_price: options.gas_price,
+		data: data.into(),
+		transaction_type: options.transaction_type,
+		max_fee_per_gas: options.max_fee_per_gas,
+		max_priority_fee_per_gas: options.max_priority_fee_per_gas,
+		..Default::default()
 	};
+	// send tx for this contract call, and return tx_hash immediately.
+	sign_and_send(config, signer, tx).await.map_err(|e| {
+		log::error!(target: MOONBEAM_SUBMIT_LOG_TARGET, "[submit error] fail to submit: {:?}", e);
+		e
+	})
+}
 
-	Ok(nonce)
+// sign `tx` by `signer` and broadcast it
+pub(crate) async fn sign_and_send(
+	config: &ConfigInstance,
+	signer: &dyn Signer,
+	tx: TransactionParameters,
+) -> Result<H256, moonbeam::Error> {
+	let raw = signer.sign_transaction(tx).await?;
+	Ok(config.moonbeam_client.eth().send_raw_transaction(raw).await?)
 }
@@ -0,0 +1,292 @@
+use std::{
+	collections::{BTreeSet, HashMap},
+	path::{Path, PathBuf},
+};
+use tokio::sync::Mutex;
+use web3::types::{BlockNumber, TransactionParameters, H256, U256};
+
+use crate::{fee, store};
+use keeper_primitives::{
+	moonbeam::{self, Fees, MOONBEAM_SUBMIT_LOG_TARGET, SELF_TRANSFER_GAS},
+	signer::{Signer, SignerRef},
+	Address, ConfigInstance, Deserialize, Serialize,
+};
+
+// gaps filled at once, a larger one needs a look by hand
+const MAX_GAP_FILLS: usize = 16;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct NonceState {
+	// the next nonce to hand out
+	next: U256,
+	// nonces handed out which are not known to be mined yet
+	pending: BTreeSet<U256>,
+}
+
+impl NonceState {
+	// forget the nonces below `mined`, they can not be used any more
+	fn prune(&mut self, mined: U256) {
+		self.pending = self.pending.split_off(&mined);
+	}
+
+	fn reserve(&mut self, in_pool: U256) -> U256 {
+		let nonce = self.next.max(in_pool);
+		self.next = nonce + 1;
+		self.pending.insert(nonce);
+		nonce
+	}
+
+	// `nonce` was used by a tx sent outside of the manager, e.g. a cancel
+	fn mark_sent(&mut self, nonce: U256) {
+		self.next = self.next.max(nonce + 1);
+		self.pending.insert(nonce);
+	}
+
+	// give back a nonce which was not sent, only the top one can be handed out again.
+	// Returns false if it leaves a gap behind.
+	fn release(&mut self, nonce: U256) -> bool {
+		if nonce + 1 == self.next {
+			self.next = nonce;
+			self.pending.remove(&nonce);
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/// Hands out the nonces of one signing key. Nonces handed out are kept on disk, so a
+/// restarted keeper neither reuses them nor leaves holes the node would wait on forever.
+pub struct NonceManager {
+	signer: SignerRef,
+	address: Address,
+	path: PathBuf,
+	state: Mutex<NonceState>,
+}
+
+impl NonceManager {
+	// load the state of the key of `signer` kept under `dir` and reconcile it with the node
+	pub async fn load(
+		config: &ConfigInstance,
+		signer: SignerRef,
+		dir: &Path,
+	) -> Result<Self, moonbeam::Error> {
+		let address = signer.address();
+		let path = state_path(dir, address);
+		let state = store::read_json(&path)?.unwrap_or_default();
+
+		let manager = NonceManager { signer, address, path, state: Mutex::new(state) };
+		manager.reconcile(config).await?;
+		Ok(manager)
+	}
+
+	pub fn address(&self) -> Address {
+		self.address
+	}
+
+	// nonces handed out which are not known to be mined yet
+	pub async fn pending(&self) -> usize {
+		self.state.lock().await.pending.len()
+	}
+
+	// the node knows the nonces up to `eth_getTransactionCount(pending)`. Nonces handed out
+	// before a restart which it does not know are gaps, and every later tx of the key waits
+	// on them. Fill them with no-op self transfers.
+	async fn reconcile(&self, config: &ConfigInstance) -> Result<(), moonbeam::Error> {
+		let eth = config.moonbeam_client.eth();
+		let mut state = self.state.lock().await;
+
+		let mined = eth.transaction_count(self.address, Some(BlockNumber::Latest)).await?;
+		let mut in_pool = eth.transaction_count(self.address, Some(BlockNumber::Pending)).await?;
+		state.prune(mined);
+		state.next = state.next.max(in_pool);
+
+		let mut fills = 0;
+		while in_pool < state.next {
+			if fills == MAX_GAP_FILLS {
+				return Err(moonbeam::Error::NonceError(format!(
+					"too many nonce gaps of {:?}, node at {:} but {:} handed out",
+					self.address, in_pool, state.next
+				)))
+			}
+			log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"[nonce] gap at {:} of {:?}, fill it",
+				in_pool,
+				self.address
+			);
+			let fees = fee::suggest_fees(config).await?;
+			send_self_transfer(config, self.signer.as_ref(), in_pool, fees).await?;
+			state.pending.insert(in_pool);
+			fills += 1;
+			// the node may hold later nonces already
+			in_pool = eth
+				.transaction_count(self.address, Some(BlockNumber::Pending))
+				.await?
+				.max(in_pool + 1);
+		}
+
+		log::info!(
+			target: MOONBEAM_SUBMIT_LOG_TARGET,
+			"[nonce] {:?} reconciled|mined: {:}|next: {:}|pending: {:}",
+			self.address,
+			mined,
+			state.next,
+			state.pending.len()
+		);
+		self.persist(&state)
+	}
+
+	// reserve the next nonce. If the node is ahead, the key was used elsewhere and the
+	// nonces in between are skipped.
+	pub async fn next(&self, config: &ConfigInstance) -> Result<U256, moonbeam::Error> {
+		let eth = config.moonbeam_client.eth();
+		let mut state = self.state.lock().await;
+
+		let mined = eth.transaction_count(self.address, Some(BlockNumber::Latest)).await?;
+		let in_pool = eth.transaction_count(self.address, Some(BlockNumber::Pending)).await?;
+		if in_pool > state.next {
+			log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"[nonce] node is ahead for {:?}|local: {:}|node: {:}",
+				self.address,
+				state.next,
+				in_pool
+			);
+		}
+		state.prune(mined);
+		let nonce = state.reserve(in_pool);
+		self.persist(&state)?;
+		Ok(nonce)
+	}
+
+	// a tx with `nonce` could not be sent. Fill the gap it leaves, if it does.
+	pub async fn release(&self, config: &ConfigInstance, nonce: U256) {
+		let mut state = self.state.lock().await;
+		if !state.release(nonce) {
+			log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"[nonce] gap at {:} of {:?}, fill it",
+				nonce,
+				self.address
+			);
+			let res = match fee::suggest_fees(config).await {
+				Ok(fees) => send_self_transfer(config, self.signer.as_ref(), nonce, fees).await,
+				Err(e) => Err(e),
+			};
+			// it is filled at the next start otherwise
+			if let Err(e) = res {
+				log::error!(
+					target: MOONBEAM_SUBMIT_LOG_TARGET,
+					"[nonce] fail to fill the gap at {:}: {:?}",
+					nonce,
+					e
+				);
+			}
+		}
+		if let Err(e) = self.persist(&state) {
+			log::error!(target: MOONBEAM_SUBMIT_LOG_TARGET, "[nonce] fail to persist: {:?}", e);
+		}
+	}
+
+	fn persist(&self, state: &NonceState) -> Result<(), moonbeam::Error> {
+		store::write_json_atomic(&self.path, state)
+	}
+}
+
+/// The nonce managers of all signing keys. Tasks sending with the same key share one.
+#[derive(Default)]
+pub struct NonceManagers(HashMap<Address, NonceManager>);
+
+impl NonceManagers {
+	pub async fn load(
+		config: &ConfigInstance,
+		signers: &[SignerRef],
+		dir: &Path,
+	) -> Result<Self, moonbeam::Error> {
+		let mut managers = HashMap::new();
+		for signer in signers {
+			let address = signer.address();
+			if !managers.contains_key(&address) {
+				managers.insert(address, NonceManager::load(config, signer.clone(), dir).await?);
+			}
+		}
+		Ok(NonceManagers(managers))
+	}
+
+	pub fn get(&self, address: Address) -> Result<&NonceManager, moonbeam::Error> {
+		self.0.get(&address).ok_or_else(|| {
+			moonbeam::Error::NonceError(format!("no nonce manager for {:?}", address))
+		})
+	}
+}
+
+fn state_path(dir: &Path, address: Address) -> PathBuf {
+	dir.join(format!("{:?}.json", address))
+}
+
+// record in the state kept under `dir` that `nonce` of `address` was used while no manager
+// was running, so that it is not handed out again
+pub(crate) fn mark_sent(dir: &Path, address: Address, nonce: U256) -> Result<(), moonbeam::Error> {
+	let path = state_path(dir, address);
+	let mut state: NonceState = store::read_json(&path)?.unwrap_or_default();
+	state.mark_sent(nonce);
+	store::write_json_atomic(&path, &state)
+}
+
+// a zero value transfer to itself, it only uses up `nonce`
+pub(crate) async fn send_self_transfer(
+	config: &ConfigInstance,
+	signer: &dyn Signer,
+	nonce: U256,
+	fees: Fees,
+) -> Result<H256, moonbeam::Error> {
+	let address = signer.address();
+	let mut tx = TransactionParameters {
+		nonce: Some(nonce),
+		to: Some(address),
+		gas: SELF_TRANSFER_GAS.into(),
+		value: U256::zero(),
+		..Default::default()
+	};
+	fees.apply_to_tx(&mut tx);
+
+	let hash = crate::sign_and_send(config, signer, tx).await?;
+	log::info!(
+		target: MOONBEAM_SUBMIT_LOG_TARGET,
+		"[nonce] self transfer {:} sent with nonce {:}",
+		hash,
+		nonce
+	);
+	Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reserve_and_release_work() {
+		let mut state = NonceState::default();
+		// the node is ahead
+		assert_eq!(state.reserve(3.into()), 3.into());
+		assert_eq!(state.reserve(3.into()), 4.into());
+		assert_eq!(state.reserve(0.into()), 5.into());
+
+		// only the top one is handed out again
+		assert!(!state.release(4.into()));
+		assert!(state.release(5.into()));
+		assert_eq!(state.next, 5.into());
+		assert_eq!(state.reserve(4.into()), 5.into());
+
+		state.prune(4.into());
+		assert_eq!(state.pending.iter().cloned().collect::<Vec<U256>>(), vec![4.into(), 5.into()]);
+
+		// a cancel below next keeps it, one above skips the nonces in between
+		state.mark_sent(4.into());
+		assert_eq!(state.next, 6.into());
+		state.mark_sent(8.into());
+		assert_eq!(state.next, 9.into());
+		assert_eq!(state.reserve(0.into()), 9.into());
+	}
+}
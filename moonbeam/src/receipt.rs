@@ -0,0 +1,93 @@
+use web3::types::{BlockId, BlockNumber, CallRequest, TransactionId, H256, U64};
+
+use keeper_primitives::{
+	moonbeam::{revert_reason, RevertKind, MOONBEAM_RESUBMIT_LOG_TARGET},
+	Address, ConfigInstance, Contract, Http, VerifyResult,
+};
+
+use crate::revert;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxStatus {
+	// no receipt yet, or it can not be fetched
+	Pending,
+	// included, waiting for more confirmations
+	Unconfirmed,
+	Succeeded(U64),
+	Reverted { block_number: U64, reason: Option<String>, kind: RevertKind },
+}
+
+// check the receipt of `hash` sent by `from`, the aggregator views tell why a submission
+// reverted. It is replayed as well to log the revert message
+pub async fn tx_status(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	hash: H256,
+	from: Address,
+	v: &VerifyResult,
+) -> TxStatus {
+	let eth = config.moonbeam_client.eth();
+	let receipt = match eth.transaction_receipt(hash).await {
+		Ok(Some(receipt)) => receipt,
+		_ => return TxStatus::Pending,
+	};
+	let (block_number, best) = match (receipt.block_number, eth.block_number().await) {
+		(Some(n), Ok(best)) => (n, best),
+		_ => return TxStatus::Pending,
+	};
+	if best + 1 < block_number + config.moonbeam_config.confirmations {
+		return TxStatus::Unconfirmed
+	}
+	if receipt.status != Some(U64::zero()) {
+		return TxStatus::Succeeded(block_number)
+	}
+
+	let reason = replay_revert_reason(config, hash, block_number).await;
+	let kind = match revert::explain(config, contract, from, v).await {
+		Ok(Some(kind)) => kind,
+		Ok(None) => RevertKind::Unknown,
+		Err(e) => {
+			log::warn!(
+				target: MOONBEAM_RESUBMIT_LOG_TARGET,
+				"fail to read why tx {:} reverted: {:?}",
+				hash,
+				e
+			);
+			RevertKind::Unknown
+		},
+	};
+
+	TxStatus::Reverted { block_number, reason, kind }
+}
+
+// a receipt has no revert data, so call the tx again on top of its block
+async fn replay_revert_reason(
+	config: &ConfigInstance,
+	hash: H256,
+	block_number: U64,
+) -> Option<String> {
+	let eth = config.moonbeam_client.eth();
+	let tx = eth.transaction(TransactionId::Hash(hash)).await.ok()??;
+	let call = CallRequest {
+		from: tx.from,
+		to: tx.to,
+		gas: Some(tx.gas),
+		value: Some(tx.value),
+		data: Some(tx.input),
+		..Default::default()
+	};
+
+	match eth.call(call, Some(BlockId::Number(BlockNumber::Number(block_number)))).await {
+		Ok(_) => None,
+		Err(e @ web3::Error::Rpc(_)) => revert_reason(&e),
+		Err(e) => {
+			log::warn!(
+				target: MOONBEAM_RESUBMIT_LOG_TARGET,
+				"fail to replay reverted tx {:}: {:?}",
+				hash,
+				e
+			);
+			None
+		},
+	}
+}
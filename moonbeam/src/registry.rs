@@ -0,0 +1,72 @@
+use tokio::time::{sleep, Duration};
+
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	registry::{self, Resolved, REGISTRY_LOG_TARGET},
+	ConfigInstance,
+};
+
+// point the shared aggregator to the one in the registry, unless it is overridden
+fn follow_aggregator(config: &ConfigInstance, resolved: &Resolved) {
+	if config.moonbeam_config.write_contract.is_some() {
+		return
+	}
+	let address = match resolved.aggregator() {
+		Some(address) if !address.is_zero() => address,
+		_ => return,
+	};
+	if address == config.aggregator_contract.get().address() {
+		return
+	}
+	match config.moonbeam_client.aggregator_contract(&format!("{:?}", address)) {
+		Ok(contract) => {
+			config.aggregator_contract.set(contract);
+			log::info!(target: REGISTRY_LOG_TARGET, "submit to the aggregator at {:?}", address);
+		},
+		Err(e) => log::error!(
+			target: REGISTRY_LOG_TARGET,
+			"fail to load the aggregator at {:?}: {:?}",
+			address,
+			e
+		),
+	}
+}
+
+/// Look the contracts up in the registry every `refresh_secs`, follow the aggregator and
+/// alert when any pointer changes
+pub async fn task_registry(config: &ConfigInstance, monitor: &MonitorSender) {
+	let interval = Duration::from_secs(config.moonbeam_config.registry.refresh_secs.max(1));
+	let mut last: Option<Resolved> = None;
+	loop {
+		match registry::resolve(&config.moonbeam_client, &config.proof_contract).await {
+			Ok(resolved) => {
+				for (name, from, to) in
+					last.as_ref().map(|l| resolved.changes(l)).unwrap_or_default()
+				{
+					log::warn!(
+						target: REGISTRY_LOG_TARGET,
+						"[changed] {} points to {:?} instead of {:?}",
+						name,
+						to,
+						from
+					);
+					let monitor_metrics = MonitorMetrics::new_with_msg(
+						REGISTRY_LOG_TARGET.to_string(),
+						None,
+						format!("registry pointer changed|{}|from:{:?}|to:{:?}", name, from, to),
+						config.name.clone(),
+					);
+					let _res = monitor.send(monitor_metrics).await;
+				}
+				follow_aggregator(config, &resolved);
+				last = Some(resolved);
+			},
+			Err(e) => log::warn!(
+				target: REGISTRY_LOG_TARGET,
+				"fail to look the contracts up in the registry: {:?}",
+				e
+			),
+		}
+		sleep(interval).await;
+	}
+}
@@ -0,0 +1,79 @@
+use web3::{
+	ethabi::{self, ParamType, Token},
+	signing::keccak256,
+	types::CallRequest,
+};
+
+use keeper_primitives::{
+	moonbeam::{RevertKind, IS_FINISHED, SUBMIT_STATUS_QUERY, SUBMIT_VERIFICATION},
+	Address, ConfigInstance, Contract, Http, VerifyResult, Web3Options,
+};
+
+// `DSAuthority.canCall`, which guards `submit` of the aggregator
+const CAN_CALL: &[u8] = b"canCall(address,address,bytes4)";
+
+// tell why the submission of `v` from `from` reverts, from the state of the aggregator rather
+// than from its revert message. None if the views find nothing wrong with it
+pub async fn explain(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	from: Address,
+	v: &VerifyResult,
+) -> web3::contract::Result<Option<RevertKind>> {
+	let has_submitted: bool = contract
+		.query(
+			SUBMIT_STATUS_QUERY,
+			(from, v.data_owner, v.request_hash),
+			None,
+			Web3Options::default(),
+			None,
+		)
+		.await?;
+	if has_submitted {
+		return Ok(Some(RevertKind::AlreadySubmitted))
+	}
+
+	let is_finished: bool = contract
+		.query(IS_FINISHED, (v.data_owner, v.request_hash), None, Web3Options::default(), None)
+		.await?;
+	if is_finished {
+		return Ok(Some(RevertKind::AlreadyFinished))
+	}
+
+	if !is_authorized(config, contract, from).await? {
+		return Ok(Some(RevertKind::NotWorker))
+	}
+
+	Ok(None)
+}
+
+// same as `DSAuth.isAuthorized` for the `submit` of the aggregator
+async fn is_authorized(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	from: Address,
+) -> web3::contract::Result<bool> {
+	let owner: Address = contract.query("owner", (), None, Web3Options::default(), None).await?;
+	if from == owner {
+		return Ok(true)
+	}
+	let authority: Address =
+		contract.query("authority", (), None, Web3Options::default(), None).await?;
+	if authority.is_zero() {
+		return Ok(false)
+	}
+
+	let selector = contract.abi().function(SUBMIT_VERIFICATION)?.short_signature();
+	let mut data = keccak256(CAN_CALL)[..4].to_vec();
+	data.extend(ethabi::encode(&[
+		Token::Address(from),
+		Token::Address(contract.address()),
+		Token::FixedBytes(selector.to_vec()),
+	]));
+	let call = CallRequest { to: Some(authority), data: Some(data.into()), ..Default::default() };
+	let output = config.moonbeam_client.eth().call(call, None).await?;
+	match ethabi::decode(&[ParamType::Bool], &output.0)?.pop() {
+		Some(Token::Bool(can_call)) => Ok(can_call),
+		_ => Ok(false),
+	}
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Balances, NonceManagers};
+use keeper_primitives::{
+	moonbeam::SchedulerPolicy, signer::SignerRef, Bytes32, ConfigInstance, VerifyResult,
+};
+
+/// Assigns each submission to one of the submit keys, the submit key and the ones of the
+/// submit pool, by the configured policy
+pub struct Scheduler {
+	signers: Vec<SignerRef>,
+	policy: SchedulerPolicy,
+	turn: AtomicUsize,
+}
+
+impl Scheduler {
+	pub fn new(config: &ConfigInstance) -> Self {
+		let mut signers: Vec<SignerRef> = vec![];
+		for signer in std::iter::once(&config.signer).chain(&config.signer_pool) {
+			if !signers.iter().any(|s| s.address() == signer.address()) {
+				signers.push(signer.clone());
+			}
+		}
+		Scheduler { signers, policy: config.moonbeam_config.scheduler, turn: AtomicUsize::new(0) }
+	}
+
+	pub fn signers(&self) -> &[SignerRef] {
+		&self.signers
+	}
+
+	// the key to submit `v` with, out of the ones not depleted if there are any
+	pub async fn pick(
+		&self,
+		v: &VerifyResult,
+		nonces: &NonceManagers,
+		balances: &Balances,
+	) -> &SignerRef {
+		let mut usable = vec![];
+		for signer in &self.signers {
+			if !balances.is_depleted(signer.address()).await {
+				usable.push(signer);
+			}
+		}
+		if usable.is_empty() {
+			usable = self.signers.iter().collect();
+		}
+
+		let mut pending = vec![];
+		if self.policy == SchedulerPolicy::LeastPending {
+			for signer in &usable {
+				// a key without nonce manager is never picked
+				let count = match nonces.get(signer.address()) {
+					Ok(manager) => manager.pending().await,
+					Err(_) => usize::MAX,
+				};
+				pending.push(count);
+			}
+		}
+		let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+		usable[pick_index(self.policy, usable.len(), turn, &v.request_hash, &pending)]
+	}
+}
+
+// index of the key out of `len` keys. `turn` counts the picks so far, `pending` holds the
+// pending txs of each key for `LeastPending`.
+fn pick_index(
+	policy: SchedulerPolicy,
+	len: usize,
+	turn: usize,
+	request_hash: &Bytes32,
+	pending: &[usize],
+) -> usize {
+	match policy {
+		SchedulerPolicy::RoundRobin => turn % len,
+		// the first one of the least busy keys
+		SchedulerPolicy::LeastPending => pending
+			.iter()
+			.enumerate()
+			.min_by_key(|(_, count)| **count)
+			.map(|(i, _)| i)
+			.unwrap_or_default(),
+		SchedulerPolicy::Sticky => {
+			let mut head = [0u8; 8];
+			head.copy_from_slice(&request_hash[..8]);
+			(u64::from_be_bytes(head) % len as u64) as usize
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pick_index_should_work() {
+		let hash = [7u8; 32];
+		let picks: Vec<usize> = (0..4)
+			.map(|turn| pick_index(SchedulerPolicy::RoundRobin, 3, turn, &hash, &[]))
+			.collect();
+		assert_eq!(picks, vec![0, 1, 2, 0]);
+
+		assert_eq!(pick_index(SchedulerPolicy::LeastPending, 3, 0, &hash, &[2, 0, 0]), 1);
+		assert_eq!(pick_index(SchedulerPolicy::LeastPending, 3, 5, &hash, &[1, 3, 2]), 0);
+
+		// the same request always goes to the same key
+		let sticky = pick_index(SchedulerPolicy::Sticky, 3, 0, &hash, &[]);
+		assert!(sticky < 3);
+		assert_eq!(pick_index(SchedulerPolicy::Sticky, 3, 9, &hash, &[]), sticky);
+		let mut other = hash;
+		other[7] += 1;
+		assert_ne!(pick_index(SchedulerPolicy::Sticky, 3, 0, &other, &[]), sticky);
+	}
+}
@@ -0,0 +1,104 @@
+use web3::{
+	contract::tokens::Tokenize,
+	types::{CallRequest, U256},
+};
+
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	moonbeam::{
+		revert_reason, Params, RevertKind, MOONBEAM_SUBMIT_LOG_TARGET, SUBMIT_VERIFICATION,
+	},
+	Address, ConfigInstance, Contract, Http, VerifyResult,
+};
+
+use crate::revert;
+
+// the submission would revert, with the decoded reason if there is one
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedRevert {
+	pub reason: Option<String>,
+	pub kind: RevertKind,
+}
+
+// run the submission with `eth_call` from the sending key. If the call can not be made at all
+// the outcome is unknown, and handled like an unknown revert. A revert which the aggregator
+// views do not explain is blamed on the submission itself.
+pub async fn simulate_submit(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+	from: Address,
+	v: &VerifyResult,
+	params: Params,
+	gas: U256,
+) -> Result<(), SimulatedRevert> {
+	let data = contract
+		.abi()
+		.function(SUBMIT_VERIFICATION)
+		.and_then(|f| f.encode_input(&params.into_tokens()))
+		.map_err(|_| SimulatedRevert { reason: None, kind: RevertKind::BadParams })?;
+	let call = CallRequest {
+		from: Some(from),
+		to: Some(contract.address()),
+		gas: Some(gas),
+		data: Some(data.into()),
+		..Default::default()
+	};
+
+	match config.moonbeam_client.eth().call(call, None).await {
+		Ok(_) => Ok(()),
+		Err(e) => {
+			let reason = revert_reason(&e);
+			// unreachable nodes are tried again later
+			if !matches!(e, web3::Error::Rpc(_)) {
+				return Err(SimulatedRevert { reason, kind: RevertKind::Unknown })
+			}
+			let kind = match revert::explain(config, contract, from, v).await {
+				Ok(Some(kind)) => kind,
+				Ok(None) => RevertKind::BadParams,
+				Err(e) => {
+					log::warn!(
+						target: MOONBEAM_SUBMIT_LOG_TARGET,
+						"fail to read why the submission reverts: {:?}",
+						e
+					);
+					RevertKind::Unknown
+				},
+			};
+			Err(SimulatedRevert { reason, kind })
+		},
+	}
+}
+
+// log a simulated revert, and alert if asked to
+pub async fn report(
+	config: &ConfigInstance,
+	monitor: &MonitorSender,
+	log_target: &str,
+	v: &VerifyResult,
+	revert: &SimulatedRevert,
+	alert: bool,
+) {
+	log::warn!(
+		target: log_target,
+		"[simulated revert] not sent|request_hash:{:}|data owner:{:}|reason:{:?}|kind:{:?}",
+		hex::encode(v.request_hash),
+		v.data_owner,
+		revert.reason,
+		revert.kind
+	);
+	if alert {
+		let monitor_metrics = MonitorMetrics::new_with_msg(
+			log_target.to_string(),
+			v.number,
+			format!(
+				"submission would revert|request_hash:{:}|data owner:{:}|reason:{:?}|kind:{:?}",
+				hex::encode(v.request_hash),
+				hex::encode(v.data_owner.0),
+				revert.reason,
+				revert.kind
+			),
+			config.name.clone(),
+		);
+		let _res = monitor.send(monitor_metrics).await;
+	}
+}
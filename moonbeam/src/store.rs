@@ -0,0 +1,60 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+	fs,
+	io::Write,
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use keeper_primitives::moonbeam;
+
+// the value kept at `path`, none if nothing was kept yet
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, moonbeam::Error> {
+	match fs::read(path) {
+		Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+// move the file at `path` aside, so that a fresh one is started, returns where it went
+pub(crate) fn set_aside(path: &Path) -> Result<PathBuf, moonbeam::Error> {
+	let secs = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default();
+	let mut aside = path.as_os_str().to_owned();
+	aside.push(format!(".corrupt-{}", secs));
+	let aside = PathBuf::from(aside);
+	fs::rename(path, &aside)?;
+	Ok(aside)
+}
+
+// write to a tmp file first, a crash never leaves a torn file behind
+pub(crate) fn write_json_atomic<T: Serialize>(
+	path: &Path,
+	value: &T,
+) -> Result<(), moonbeam::Error> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(".tmp");
+	fs::write(&tmp, serde_json::to_vec(value)?)?;
+	fs::rename(&tmp, path)?;
+	Ok(())
+}
+
+// append `value` as one line to the file at `path`
+pub(crate) fn append_json_line<T: Serialize>(
+	path: &Path,
+	value: &T,
+) -> Result<(), moonbeam::Error> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let mut line = serde_json::to_vec(value)?;
+	line.push(b'\n');
+	fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)?;
+	Ok(())
+}
@@ -0,0 +1,129 @@
+use std::collections::LinkedList;
+use web3::types::{BlockNumber, H256, U256};
+
+use crate::{nonce, store, task::RetryTx, NonceManager};
+use keeper_primitives::{
+	monitor::{MonitorMetrics, MonitorSender},
+	moonbeam::{self, Fees, MOONBEAM_RESUBMIT_LOG_TARGET},
+	signer::Signer,
+	ConfigInstance,
+};
+
+// the nonce to resubmit `item` at: its own one while the tx there may still be replaced,
+// none if a fresh one is needed. A stuck tx is cancelled with `fees` first. The nonce of
+// another key is never reused, it was sent by the submit task or a pool key.
+pub(crate) async fn resubmit_nonce(
+	config: &ConfigInstance,
+	signer: &dyn Signer,
+	nonces: &NonceManager,
+	item: &RetryTx,
+	fees: Fees,
+	monitor: &MonitorSender,
+) -> Result<Option<U256>, moonbeam::Error> {
+	let (hash, nonce) = match (item.fat_tx.tx.0, item.fat_tx.nonce) {
+		(Some(hash), Some(nonce)) => (hash, nonce),
+		_ => return Ok(None),
+	};
+	if item.fat_tx.sender(config) != signer.address() {
+		return Ok(None)
+	}
+	let eth = config.moonbeam_client.eth();
+	// used up by an earlier attempt, or by a cancel
+	let mined = eth.transaction_count(nonces.address(), Some(BlockNumber::Latest)).await?;
+	if nonce < mined {
+		return Ok(None)
+	}
+
+	let best = eth.block_number().await?;
+	let since = item.nonce_sent_at.unwrap_or(item.fat_tx.send_at);
+	if !config.moonbeam_config.stuck.is_stuck(item.bumps, since, best) {
+		return Ok(Some(nonce))
+	}
+
+	let cancel = nonce::send_self_transfer(config, signer, nonce, fees).await?;
+	let v = &item.fat_tx.tx.1;
+	log::warn!(
+		target: MOONBEAM_RESUBMIT_LOG_TARGET,
+		"[stuck] tx {:} at nonce {:} cancelled by {:}|bumps: {:}|since: #{:}|request_hash: {:}",
+		hash,
+		nonce,
+		cancel,
+		item.bumps,
+		since,
+		hex::encode(v.request_hash)
+	);
+	let monitor_metrics = MonitorMetrics::new_with_msg(
+		MOONBEAM_RESUBMIT_LOG_TARGET.to_string(),
+		Some(best),
+		format!(
+			"stuck tx cancelled|tx:{:}|nonce:{:}|cancel tx:{:}|request_hash:{:}|bumps:{:}",
+			hash,
+			nonce,
+			cancel,
+			hex::encode(v.request_hash),
+			item.bumps
+		),
+		config.name.clone(),
+	);
+	let _res = monitor.send(monitor_metrics).await;
+
+	Ok(None)
+}
+
+/// Cancel whatever `signer` has pending at `nonce` with a self transfer. The fees of the pending
+/// tx, as kept in the retry queue, are bumped to replace it. `max_fee` in wei is the max fee per
+/// gas of the cancel, or its gas price for a legacy tx. The nonce is recorded as used, and the
+/// request of the cancelled tx is submitted again with a fresh one.
+pub async fn cancel_tx(
+	config: &ConfigInstance,
+	signer: &dyn Signer,
+	nonce: U256,
+	max_fee: Option<U256>,
+) -> Result<H256, moonbeam::Error> {
+	let address = signer.address();
+	let queue_path = &config.channel_files.retry_queue;
+	let mut queue: LinkedList<RetryTx> =
+		store::read_json(queue_path).ok().flatten().unwrap_or_default();
+	let cancelled = queue
+		.iter_mut()
+		.find(|i| i.fat_tx.nonce == Some(nonce) && i.fat_tx.sender(config) == address);
+
+	let suggested = crate::fee::suggest_fees(config).await?;
+	let bump = &config.moonbeam_config.fee.bump;
+	let fees = match cancelled.as_ref().and_then(|i| i.fat_tx.fees) {
+		Some(pending) => pending.bumped(suggested, bump),
+		None => {
+			log::warn!(
+				target: MOONBEAM_RESUBMIT_LOG_TARGET,
+				"[cancel] fees of the tx at nonce {:} are unknown, bump the suggested ones, pass --max-fee if it is not replaced",
+				nonce
+			);
+			suggested.bumped(suggested, bump)
+		},
+	};
+	let fees = match (max_fee, fees) {
+		(Some(max_fee), Fees::Legacy { .. }) => Fees::Legacy { gas_price: max_fee },
+		(Some(max_fee), Fees::Eip1559 { max_priority_fee_per_gas, .. }) => Fees::Eip1559 {
+			max_fee_per_gas: max_fee,
+			max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee),
+		},
+		(None, fees) => fees,
+	};
+	let hash = nonce::send_self_transfer(config, signer, nonce, fees).await?;
+
+	// a restarted keeper must not hand the nonce out again
+	nonce::mark_sent(&config.channel_files.nonces, address, nonce)?;
+	if let Some(item) = cancelled {
+		item.fat_tx.tx.0 = None;
+		item.fat_tx.nonce = None;
+		item.nonce_sent_at = None;
+		item.bumps = 0;
+		log::info!(
+			target: MOONBEAM_RESUBMIT_LOG_TARGET,
+			"[cancel] request_hash {:} is submitted again with a fresh nonce",
+			hex::encode(item.fat_tx.tx.1.request_hash)
+		);
+		store::write_json_atomic(queue_path, &queue)?;
+	}
+	Ok(hash)
+}
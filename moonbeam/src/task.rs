@@ -1,20 +1,27 @@
-use crate::{TxHashAndInfo, U64};
+use crate::{store, Balances, NonceManagers, RequestTracker, Scheduler, TxHashAndInfo, U64};
 
 use codec::Encode;
-use std::{collections::linked_list::LinkedList, sync::Arc};
+use std::{
+	collections::{linked_list::LinkedList, HashMap},
+	path::Path,
+	sync::Arc,
+};
 
 use keeper_primitives::{
+	monitor::MonitorSender,
 	moonbeam::{
-		MOONBEAM_RESUBMIT_LOG_TARGET, MOONBEAM_SCAN_LOG_TARGET, MOONBEAM_SUBMIT_LOG_TARGET,
-		RESUBMIT_INTERVAL,
+		self, Fees, IS_FINISHED, MOONBEAM_RESUBMIT_LOG_TARGET, MOONBEAM_SCAN_LOG_TARGET,
+		MOONBEAM_SUBMIT_LOG_TARGET, RESUBMIT_INTERVAL,
 	},
-	ConfigInstance, Delay, Deserialize, Error, Hash, MqReceiver, MqSender, Serialize, VerifyResult,
+	signer::Signer,
+	Address, ConfigInstance, Contract, Delay, Deserialize, Error, Hash, Http, MqReceiver, MqSender,
+	Serialize, VerifyResult, Web3Options,
 };
 use tokio::{
 	sync::Mutex,
 	time::{sleep, Duration},
 };
-use web3::types::{H256, U256};
+use web3::types::{BlockNumber, H256, U256};
 
 use super::KeeperResult;
 
@@ -22,8 +29,16 @@ use super::KeeperResult;
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct FatTx {
 	pub send_at: U64,
-	pub gas_price: U256,
+	// fees the tx was sent with, none if left to the node
+	#[serde(default)]
+	pub fees: Option<Fees>,
 	pub nonce: Option<U256>,
+	// gas limit the tx was sent with
+	#[serde(default)]
+	pub gas: Option<U256>,
+	// the key it was sent with
+	#[serde(default)]
+	pub from: Option<Address>,
 	pub tx: TxHashAndInfo,
 }
 
@@ -31,12 +46,26 @@ impl FatTx {
 	pub(crate) fn new_with_tx_info(tx: TxHashAndInfo) -> Self {
 		FatTx { tx, ..Default::default() }
 	}
+
+	// the key it was sent with, kept before the sender was recorded it was the submit key
+	pub(crate) fn sender(&self, config: &ConfigInstance) -> Address {
+		self.from.unwrap_or_else(|| config.signer.address())
+	}
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct RetryTx {
 	pub fat_tx: FatTx,
 	pub retry_times: u8,
+	// how many submissions of it have reverted
+	#[serde(default)]
+	pub reverts: u8,
+	// how many times the tx at its nonce was replaced
+	#[serde(default)]
+	pub bumps: u8,
+	// when its nonce was first sent
+	#[serde(default)]
+	pub nonce_sent_at: Option<U64>,
 }
 
 impl RetryTx {
@@ -44,18 +73,27 @@ impl RetryTx {
 		self.fat_tx.tx.clone()
 	}
 
-	// clear retry_times, update nonce, price, send_at and tx_hash
+	// clear retry_times, update sender, nonce, fees, send_at and tx_hash
+	// a resubmission at the same nonce is a bump
 	pub(crate) fn update_after_resubmit(
 		&mut self,
+		from: Address,
 		nonce: U256,
-		gas_price: U256,
+		fees: Fees,
 		send_at: U64,
 		tx_hash: Option<H256>,
 	) {
+		if self.fat_tx.nonce == Some(nonce) && self.nonce_sent_at.is_some() {
+			self.bumps = self.bumps.saturating_add(1);
+		} else {
+			self.bumps = 0;
+			self.nonce_sent_at = Some(send_at);
+		}
 		self.retry_times = 0;
+		self.fat_tx.from = Some(from);
 		self.fat_tx.tx.0 = tx_hash;
 		self.fat_tx.nonce = Some(nonce);
-		self.fat_tx.gas_price = gas_price;
+		self.fat_tx.fees = Some(fees);
 		self.fat_tx.send_at = send_at;
 	}
 }
@@ -66,6 +104,112 @@ pub fn create_retry_queue() -> RetryQueue {
 	Arc::new(Mutex::new(LinkedList::<RetryTx>::new()))
 }
 
+// keep the queue at `path`, a failure is only logged and the next decision tries again
+pub(crate) fn persist_retry_queue(path: &Path, queue: &LinkedList<RetryTx>) {
+	if let Err(e) = store::write_json_atomic(path, queue) {
+		log::error!(
+			target: MOONBEAM_RESUBMIT_LOG_TARGET,
+			"[queue] fail to persist the retry queue: {:?}",
+			e
+		);
+	}
+}
+
+// the queue kept at `config.channel_files.retry_queue`, reconciled with the chain:
+// - finished requests are dropped
+// - a tx without receipt whose nonce is mined was replaced, it is resubmitted right away
+// an item is kept as it is if the chain can not be asked, and a corrupt file is moved aside
+pub async fn load_retry_queue(
+	config: &ConfigInstance,
+	contract: &Contract<Http>,
+) -> Result<RetryQueue, moonbeam::Error> {
+	let path = &config.channel_files.retry_queue;
+	let kept: LinkedList<RetryTx> = match store::read_json(path) {
+		Ok(kept) => kept.unwrap_or_default(),
+		Err(moonbeam::Error::StoreParseError(e)) => {
+			let aside = store::set_aside(path)?;
+			log::error!(
+				target: MOONBEAM_RESUBMIT_LOG_TARGET,
+				"[queue] fail to parse the kept retry queue, moved to {:?}: {:?}",
+				aside,
+				e
+			);
+			LinkedList::new()
+		},
+		Err(e) => return Err(e),
+	};
+	let eth = config.moonbeam_client.eth();
+	// mined nonces of every key the kept txs were sent with
+	let mut mined: HashMap<Address, U256> = HashMap::new();
+
+	let total = kept.len();
+	let mut queue = LinkedList::new();
+	for mut item in kept {
+		let v = item.tx_info().1;
+		let is_finished: Result<bool, _> = contract
+			.query(IS_FINISHED, (v.data_owner, v.request_hash), None, Web3Options::default(), None)
+			.await;
+		match is_finished {
+			Ok(true) => continue,
+			Ok(false) => {},
+			Err(e) => {
+				log::warn!(
+					target: MOONBEAM_RESUBMIT_LOG_TARGET,
+					"[queue] fail to check if request {:} is finished, keep it: {:?}",
+					hex::encode(v.request_hash),
+					e
+				);
+				queue.push_back(item);
+				continue
+			},
+		}
+		if let (Some(hash), Some(nonce)) = (item.tx_info().0, item.fat_tx.nonce) {
+			let from = item.fat_tx.sender(config);
+			let mined = match mined.get(&from) {
+				Some(mined) => Some(*mined),
+				None => match eth.transaction_count(from, Some(BlockNumber::Latest)).await {
+					Ok(count) => Some(*mined.entry(from).or_insert(count)),
+					Err(e) => {
+						log::warn!(
+							target: MOONBEAM_RESUBMIT_LOG_TARGET,
+							"[queue] fail to fetch the nonce of {:?}: {:?}",
+							from,
+							e
+						);
+						None
+					},
+				},
+			};
+			// left as it is if unknown, the resubmit task checks it again
+			let replaced = match mined {
+				Some(mined) if nonce < mined =>
+					eth.transaction_receipt(hash).await.map(|r| r.is_none()).unwrap_or(false),
+				_ => false,
+			};
+			if replaced {
+				log::warn!(
+					target: MOONBEAM_RESUBMIT_LOG_TARGET,
+					"[queue] tx {:} replaced at nonce {:}|request_hash: {:}",
+					hash,
+					nonce,
+					hex::encode(v.request_hash)
+				);
+				item.fat_tx.tx.0 = None;
+			}
+		}
+		queue.push_back(item);
+	}
+
+	log::info!(
+		target: MOONBEAM_RESUBMIT_LOG_TARGET,
+		"[queue] {:} of {:} kept submissions still need resubmitting",
+		queue.len(),
+		total
+	);
+	persist_retry_queue(path, &queue);
+	Ok(Arc::new(Mutex::new(queue)))
+}
+
 pub async fn task_scan(
 	config: &ConfigInstance,
 	msg_sender: &mut MqSender,
@@ -79,11 +223,11 @@ pub async fn task_scan(
 			Ok(b) => b,
 			Err(e) => {
 				log::error!(
-						target: MOONBEAM_SCAN_LOG_TARGET,
-						"Fail to get latest block number in tasks moonbeam scan, after #{:?} scanned, err is {:?}",
-						start,
-						 e
-					);
+					target: MOONBEAM_SCAN_LOG_TARGET,
+					"Fail to get latest block number in tasks moonbeam scan, after #{:?} scanned, err is {:?}",
+					start,
+					 e
+				);
 				return Err((None, e.into()))
 			},
 		};
@@ -141,6 +285,11 @@ pub async fn task_submit(
 	config: &ConfigInstance,
 	msg_queue: (&mut MqSender, &mut MqReceiver),
 	last_sent_tx: &mut FatTx,
+	monitor: &MonitorSender,
+	scheduler: &Scheduler,
+	nonces: &NonceManagers,
+	balances: &Balances,
+	tracker: &RequestTracker,
 ) -> Result<(), (Option<U64>, Error)> {
 	while let Ok(r) = msg_queue.1.recv_timeout(Delay::new(Duration::from_secs(2))).await {
 		// while let Ok(events) = event_receiver.recv().await {
@@ -149,6 +298,17 @@ pub async fn task_submit(
 			None => continue,
 		};
 
+		// leave the batch in the channel until a submit key is funded again
+		if balances.all_depleted(scheduler.signers()).await {
+			log::warn!(
+				target: MOONBEAM_SUBMIT_LOG_TARGET,
+				"[balance] all submit keys are below the floor, hold the submissions"
+			);
+			msg.rollback().map_err(|e| (None, e.into()))?;
+			sleep(Duration::from_secs(config.moonbeam_config.balance.interval_secs.max(1))).await;
+			continue
+		}
+
 		let input_str = std::str::from_utf8(&*msg).expect("wrong format of msg into submit task");
 		let inputs: (Hash, Vec<VerifyResult>) = serde_json::from_str(input_str)
 			.map_err(|e| {
@@ -168,11 +328,15 @@ pub async fn task_submit(
 		// enter submit process.
 		let res = super::submit_txs(
 			config,
-			&config.aggregator_contract,
-			config.private_key,
+			&config.aggregator_contract.get(),
+			scheduler,
+			nonces,
+			balances,
+			tracker,
 			inputs.1,
 			// nonce,
 			last_sent_tx,
+			monitor,
 		)
 		.await;
 
@@ -202,6 +366,10 @@ pub async fn task_resubmit(
 	msg_receiver: &mut MqReceiver,
 	queue: RetryQueue,
 	local_last_sent_at: &mut U64,
+	monitor: &MonitorSender,
+	nonces: &NonceManagers,
+	balances: &Balances,
+	tracker: &RequestTracker,
 ) -> Result<(), (Option<U64>, Error)> {
 	while let Ok(r) = msg_receiver.recv_timeout(Delay::new(Duration::from_secs(1))).await {
 		// todo: change the architect to remove duplicated code
@@ -239,14 +407,29 @@ pub async fn task_resubmit(
 						// channel
 						if &last_sent_at_from_outer > local_last_sent_at {
 							for tx_info in inputs.1 {
+								// the kept queue may hold it already after a restart
+								let v = &tx_info.tx.1;
+								if q.iter().any(|i| {
+									i.fat_tx.tx.1.request_hash == v.request_hash &&
+										i.fat_tx.tx.1.data_owner == v.data_owner
+								}) {
+									continue
+								}
 								// discard the option and sent_at received from the last task
 								// because task resubmit use a new private key
+								// but keep the gas limit and fees to bump from
 								q.push_back(RetryTx {
-									fat_tx: FatTx::new_with_tx_info(tx_info.tx),
+									fat_tx: FatTx {
+										gas: tx_info.gas,
+										fees: tx_info.fees,
+										from: tx_info.from,
+										..FatTx::new_with_tx_info(tx_info.tx)
+									},
 									..Default::default()
 								});
 								*local_last_sent_at = tx_info.send_at;
 							}
+							persist_retry_queue(&config.channel_files.retry_queue, &q);
 						}
 
 						log::debug!(
@@ -270,12 +453,16 @@ pub async fn task_resubmit(
 				// will consume the queue from the front
 				// will throw error if:
 				// - updating nonce fails
-				// - updating gas price fails
+				// - updating fees fails
 				let res = super::resubmit_txs(
 					config,
-					&config.aggregator_contract,
-					config.private_key_optional,
+					&config.aggregator_contract.get(),
+					config.signer_optional.as_ref(),
+					nonces,
+					balances,
+					tracker,
 					queue.clone(),
+					monitor,
 				)
 				.await;
 
@@ -297,12 +484,16 @@ pub async fn task_resubmit(
 				// will consume the queue from the front
 				// will throw error if:
 				// - updating nonce fails
-				// - updating gas price fails
+				// - updating fees fails
 				super::resubmit_txs(
 					config,
-					&config.aggregator_contract,
-					config.private_key_optional,
+					&config.aggregator_contract.get(),
+					config.signer_optional.as_ref(),
+					nonces,
+					balances,
+					tracker,
 					queue.clone(),
+					monitor,
 				)
 				.await
 				.map_err(|e| {
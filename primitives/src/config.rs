@@ -2,8 +2,7 @@ use super::{
 	Contract, Deserialize, Http, IpfsClient, IpfsConfig, KiltClient, KiltConfig, MoonbeamClient,
 	MoonbeamConfig, Serialize,
 };
-use crate::monitor::MonitorConfig;
-use secp256k1::SecretKey;
+use crate::{monitor::MonitorConfig, registry::SharedContract, signer::SignerRef};
 use std::{fs::File, path::PathBuf};
 
 // todo: move
@@ -13,6 +12,14 @@ pub struct ChannelFiles {
 	pub verify_to_attest: PathBuf,
 	pub attest_to_submit: PathBuf,
 	pub resubmit: PathBuf,
+	// where the nonce managers keep their state
+	pub nonces: PathBuf,
+	// where the resubmit task keeps its retry queue
+	pub retry_queue: PathBuf,
+	// where the aggregator scan keeps the requests it has seen
+	pub requests: PathBuf,
+	// where verdicts which differ from the ones of other keepers are logged
+	pub audit_log: PathBuf,
 }
 
 // todo move
@@ -23,10 +30,17 @@ pub struct ConfigInstance {
 	pub moonbeam_client: MoonbeamClient,
 	pub ipfs_client: IpfsClient,
 	pub kilt_client: KiltClient,
+	pub kilt_config: KiltConfig,
+	pub moonbeam_config: MoonbeamConfig,
 	pub proof_contract: Contract<Http>,
-	pub aggregator_contract: Contract<Http>,
-	pub private_key: SecretKey,
-	pub private_key_optional: Option<SecretKey>,
+	// swapped when the registry points to another aggregator
+	pub aggregator_contract: SharedContract,
+	// signs with the submit key
+	pub signer: SignerRef,
+	// signs with the resubmit key
+	pub signer_optional: Option<SignerRef>,
+	// sign with the extra submit keys
+	pub signer_pool: Vec<SignerRef>,
 	#[cfg(feature = "monitor")]
 	pub bot_url: String,
 }
@@ -76,11 +90,34 @@ mod tests {
 			moonbeam: MoonbeamConfig {
 				url: "http://127.0.0.1:7545".to_string(),
 				read_contract: "read_contract".to_string(),
-				write_contract: "write_contract".to_string(),
-				private_key: "private_key".to_string(),
+				write_contract: Some("write_contract".to_string()),
+				registry: Default::default(),
+				private_key: Some("private_key".to_string()),
+				private_key_optional: None,
+				keystore: None,
+				keystore_optional: None,
+				remote_signer: None,
+				remote_signer_optional: None,
+				submit_pool: vec![],
+				scheduler: Default::default(),
+				balance: Default::default(),
+				skip_rejected: false,
+				gas: Default::default(),
+				fee: Default::default(),
+				confirmations: 1,
+				stuck: Default::default(),
 			},
 			ipfs: IpfsConfig { base_url: "https://ipfs.infura.io:5001".to_string() },
-			kilt: KiltConfig { url: "kilt_url".to_string() },
+			kilt: KiltConfig {
+				url: "kilt_url".to_string(),
+				backup_urls: vec![],
+				verify_storage_proof: false,
+				finality_checkpoint: None,
+				insecure_trust_node: false,
+				trusted_attesters: Default::default(),
+				trusted_attesters_file: None,
+				attestation_cache: None,
+			},
 		};
 
 		assert_eq!(config, expect);
@@ -29,6 +29,12 @@ pub enum Error {
 	#[error("Parse private Error, err: {0}")]
 	PrivateKeyError(#[from] secp256k1::Error),
 
+	#[error("Load signing key Error, err: {0}")]
+	KeystoreError(#[from] crate::keystore::Error),
+
+	#[error("Signer Error, err: {0}")]
+	SignerError(#[from] crate::signer::Error),
+
 	#[error("Task error, err: {0}")]
 	TaskJoinError(#[from] tokio::task::JoinError),
 
@@ -0,0 +1,167 @@
+use rand::Rng;
+use secp256k1::SecretKey;
+use std::{
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+use super::{Deserialize, Key, SecretKeyRef, Serialize};
+
+pub const KEYSTORE_LOG_TARGET: &str = "Keystore";
+
+/// Where the password of a keystore comes from: a file, else an environment variable,
+/// else an interactive prompt
+#[derive(Eq, PartialEq, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PasswordSource {
+	#[serde(default)]
+	pub password_file: Option<PathBuf>,
+	#[serde(default)]
+	pub password_env: Option<String>,
+}
+
+impl PasswordSource {
+	pub fn read(&self, prompt: &str) -> Result<String> {
+		self.read_with(prompt, false)
+	}
+
+	// a password for a new keystore, a prompted one is asked twice
+	pub fn read_new(&self, prompt: &str) -> Result<String> {
+		self.read_with(prompt, true)
+	}
+
+	fn read_with(&self, prompt: &str, confirm: bool) -> Result<String> {
+		if let Some(file) = &self.password_file {
+			let password = std::fs::read_to_string(file)?;
+			return Ok(password.trim_end_matches(&['\r', '\n'][..]).to_owned())
+		}
+		if let Some(env) = &self.password_env {
+			return std::env::var(env)
+				.map_err(|_| Error::PasswordError(format!("env {} is not set", env)))
+		}
+
+		let password = rpassword::prompt_password(prompt)?;
+		if confirm && rpassword::prompt_password("Repeat the password: ")? != password {
+			return Err(Error::PasswordError("passwords do not match".to_owned()))
+		}
+		Ok(password)
+	}
+}
+
+/// An Ethereum V3 JSON keystore, encrypted with scrypt or pbkdf2
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct KeystoreConfig {
+	pub path: PathBuf,
+	#[serde(flatten)]
+	pub password: PasswordSource,
+}
+
+impl KeystoreConfig {
+	pub fn load(&self) -> Result<SecretKey> {
+		let password = self.password.read(&format!("Password of {}: ", self.path.display()))?;
+		decrypt(&self.path, password)
+	}
+}
+
+pub fn decrypt(path: &Path, password: String) -> Result<SecretKey> {
+	let bytes = eth_keystore::decrypt_key(path, password)?;
+	Ok(SecretKey::from_slice(&bytes)?)
+}
+
+// encrypt `key` into a new keystore under `dir`, named by its address
+pub fn encrypt(dir: &Path, key: &SecretKey, password: String) -> Result<PathBuf> {
+	std::fs::create_dir_all(dir)?;
+	let name = format!("{:?}", SecretKeyRef::new(key).address());
+	eth_keystore::encrypt_key(dir, &mut rand::thread_rng(), &key[..], password, Some(&name))?;
+	Ok(dir.join(name))
+}
+
+pub fn generate() -> SecretKey {
+	loop {
+		// almost every 32 bytes make a valid key
+		if let Ok(key) = SecretKey::from_slice(&rand::thread_rng().gen::<[u8; 32]>()) {
+			return key
+		}
+	}
+}
+
+// the key kept in `keystore`, else the `plaintext` one if allowed
+pub fn signing_key(
+	keystore: &Option<KeystoreConfig>,
+	plaintext: &Option<String>,
+	name: &str,
+	allow_plaintext: bool,
+) -> Result<Option<SecretKey>> {
+	match (keystore, plaintext) {
+		(Some(keystore), _) => keystore.load().map(Some),
+		(None, Some(_)) if !allow_plaintext => Err(Error::PlaintextNotAllowed(name.to_owned())),
+		(None, Some(plaintext)) => {
+			log::warn!(target: KEYSTORE_LOG_TARGET, "{} is kept in plaintext", name);
+			Ok(Some(SecretKey::from_str(plaintext.trim_start_matches("0x"))?))
+		},
+		(None, None) => Ok(None),
+	}
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("Keystore Error: {0}")]
+	KeystoreError(#[from] eth_keystore::KeystoreError),
+
+	#[error("Keystore I/O Error: {0}")]
+	IoError(#[from] std::io::Error),
+
+	#[error("Invalid Private Key: {0}")]
+	InvalidKey(#[from] secp256k1::Error),
+
+	#[error("Password Error: {0}")]
+	PasswordError(String),
+
+	#[error("No key is configured for {0}")]
+	MissingKey(String),
+
+	#[error("{0} is in plaintext, pass --allow-plaintext-key to use it")]
+	PlaintextNotAllowed(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keystore_should_work() {
+		// a fresh directory, runs in parallel do not share keystores
+		let dir = std::env::temp_dir().join(format!(
+			"keeper-keystore-test-{}-{:x}",
+			std::process::id(),
+			rand::random::<u64>()
+		));
+		let key = generate();
+		let path = encrypt(&dir, &key, "password".to_owned()).unwrap();
+
+		std::env::set_var("KEEPER_KEYSTORE_TEST_PASSWORD", "password");
+		let keystore = KeystoreConfig {
+			path: path.clone(),
+			password: PasswordSource {
+				password_file: None,
+				password_env: Some("KEEPER_KEYSTORE_TEST_PASSWORD".to_owned()),
+			},
+		};
+		assert_eq!(keystore.load().unwrap(), key);
+		assert!(decrypt(&path, "wrong".to_owned()).is_err());
+
+		let _ = std::fs::remove_dir_all(dir);
+	}
+
+	#[test]
+	fn plaintext_needs_opt_in() {
+		let plaintext = Some(hex::encode(&generate()[..]));
+		assert!(matches!(
+			signing_key(&None, &plaintext, "private_key", false),
+			Err(Error::PlaintextNotAllowed(_))
+		));
+		assert!(signing_key(&None, &plaintext, "private_key", true).unwrap().is_some());
+		assert!(signing_key(&None, &None, "private_key", false).unwrap().is_none());
+	}
+}
@@ -3,18 +3,43 @@ use codec::{Decode, Encode};
 use frame_metadata::StorageHasher;
 use jsonrpsee::{
 	http_client::{HttpClient, HttpClientBuilder},
-	types::{to_json_value, traits::Client, Error as RpcError},
+	types::{to_json_value, traits::Client, DeserializeOwned, Error as RpcError, JsonValue},
+	ws_client::{WsClient, WsClientBuilder},
 };
-use sp_runtime::AccountId32 as AccountId;
+use sp_core::storage::StorageChangeSet;
+use sp_runtime::{generic, traits::BlakeTwo256, AccountId32 as AccountId};
+use std::{
+	collections::BTreeMap,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+};
+use tokio::sync::RwLock;
 
 pub const KILT_LOG_TARGET: &str = "KILT";
-const ATTESTATION_PALLET_PREFIX: &'static str = "Attestation";
-const ATTESTATION_STORAGE_PREFIX: &'static str = "Attestations";
+pub const ATTESTATION_PALLET_PREFIX: &'static str = "Attestation";
+pub const ATTESTATION_STORAGE_PREFIX: &'static str = "Attestations";
 const HASHER: StorageHasher = StorageHasher::Blake2_128Concat;
+pub const DELEGATION_PALLET_PREFIX: &'static str = "Delegation";
+pub const DELEGATION_NODES_STORAGE_PREFIX: &'static str = "DelegationNodes";
+pub const GRANDPA_PALLET_PREFIX: &'static str = "Grandpa";
+pub const GRANDPA_SET_ID_STORAGE_PREFIX: &'static str = "CurrentSetId";
+pub const DID_PALLET_PREFIX: &'static str = "Did";
+pub const DID_STORAGE_PREFIX: &'static str = "Did";
+pub const DID_BLACKLIST_STORAGE_PREFIX: &'static str = "DidBlacklist";
+pub const WEB3_NAMES_PALLET_PREFIX: &'static str = "Web3Names";
+pub const WEB3_NAMES_STORAGE_PREFIX: &'static str = "Names";
+pub const KILT_SS58_PREFIX: u16 = 38;
 pub const KILT_MAX_RETRY_TIMES: usize = 5;
+// secs between two health checks of the kilt endpoints
+pub const KILT_HEALTH_CHECK_INTERVAL: u64 = 30;
 
 //fixme: make generic
 pub type Balance = u128;
+pub type BlockNumber = u64;
+pub type Header = generic::Header<BlockNumber, BlakeTwo256>;
 
 #[derive(Default, Clone, Debug, Encode, Decode, PartialEq, Serialize, Deserialize)]
 pub struct Deposit<Account, Balance> {
@@ -22,11 +47,12 @@ pub struct Deposit<Account, Balance> {
 	pub amount: Balance,
 }
 
+// the latest attestation layout, values stored by older runtimes
+// are converted into it by `component-kilt`
 #[derive(Clone, Debug, Encode, Decode, PartialEq)]
 pub struct AttestationDetails<Hash: Encode + Clone, Account, Balance> {
 	pub ctype_hash: Hash,
 	pub attester: Account,
-	// pub delegation_id: Option<Hash>,
 	pub authorization_id: Option<AuthorizationId<Hash>>,
 	pub revoked: bool,
 	pub deposit: Deposit<Account, Balance>,
@@ -39,26 +65,276 @@ pub enum AuthorizationId<DelegationId> {
 
 pub type Attestation = AttestationDetails<Hash, AccountId, Balance>;
 
+/// Why the kilt component rejects a verify result
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+	#[error("no attestation for the root hash")]
+	NotAttested,
+	#[error("attestation is revoked")]
+	Revoked,
+	#[error("ctype 0x{} is claimed but 0x{} is attested", hex::encode(.claimed), hex::encode(.attested))]
+	CTypeMismatch { claimed: Bytes32, attested: Bytes32 },
+	#[error("attester 0x{} is claimed but 0x{} has attested", hex::encode(.claimed), hex::encode(.attested))]
+	AttesterMismatch { claimed: Bytes32, attested: Bytes32 },
+	#[error("attester 0x{} is not trusted for ctype 0x{}", hex::encode(.attester), hex::encode(.c_type))]
+	UntrustedAttester { c_type: Bytes32, attester: Bytes32 },
+	#[error("delegation node 0x{} does not exist", hex::encode(.0))]
+	DelegationNotFound(Bytes32),
+	#[error("delegation node 0x{} is revoked", hex::encode(.0))]
+	DelegationRevoked(Bytes32),
+	#[error("delegation node 0x{} has no attest permission", hex::encode(.0))]
+	DelegationCannotAttest(Bytes32),
+	#[error("delegation node 0x{} is not owned by the attester", hex::encode(.0))]
+	DelegationNotOwned(Bytes32),
+	#[error("delegation hierarchy of 0x{} is cyclic or too deep", hex::encode(.0))]
+	DelegationHierarchyInvalid(Bytes32),
+	#[error("delegation node 0x{} can not be checked with this kilt runtime", hex::encode(.0))]
+	DelegationUnsupported(Bytes32),
+	#[error("did of attester 0x{} has been deleted", hex::encode(.0))]
+	DidDeleted(Bytes32),
+}
+
+impl RejectReason {
+	// the user claimed something else than what is attested on kilt
+	pub fn is_mismatch(&self) -> bool {
+		matches!(self, RejectReason::CTypeMismatch { .. } | RejectReason::AttesterMismatch { .. })
+	}
+}
+
+/// The full DID of an attester and its web3name, if claimed
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct AttesterIdentity {
+	// did:kilt:<ss58 address>
+	pub did: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub web3name: Option<String>,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct KiltConfig {
 	pub url: String,
+	// endpoints to fail over to when `url` is unreachable or unhealthy, in order of preference
+	#[serde(default)]
+	pub backup_urls: Vec<String>,
+	// check attestations against a storage proof of a GRANDPA finalized block
+	// instead of trusting the value returned by the rpc node
+	#[serde(default)]
+	pub verify_storage_proof: bool,
+	// trusted grandpa authority set to start from, required with `verify_storage_proof`
+	#[serde(default)]
+	pub finality_checkpoint: Option<FinalityCheckpoint>,
+	// set by `--insecure-trust-kilt-node`, take the authority set from the node if no
+	// checkpoint is configured
+	#[serde(skip)]
+	pub insecure_trust_node: bool,
+	// ctype hash => trusted attester accounts or dids, if empty every attester is trusted
+	#[serde(default)]
+	pub trusted_attesters: BTreeMap<String, Vec<String>>,
+	// json file in the same format as `trusted_attesters`, reloaded when modified
+	#[serde(default)]
+	pub trusted_attesters_file: Option<PathBuf>,
+	// cache valid attestations, disabled if absent
+	#[serde(default)]
+	pub attestation_cache: Option<AttestationCacheConfig>,
 }
 
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct AttestationCacheConfig {
+	// how long an attestation is served from the cache at most
+	pub ttl_secs: u64,
+	// websocket endpoint watching the cached attestations for revocation
+	pub ws_url: String,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct FinalityCheckpoint {
+	// the chain the set belongs to, checked against the genesis block of the node
+	pub genesis_hash: Hash,
+	pub set_id: u64,
+	// (hex encoded ed25519 public key, weight)
+	pub authorities: Vec<(String, u64)>,
+}
+
+/// Response of `state_getRuntimeVersion`, only the fields the keeper cares about
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeVersion {
+	pub spec_name: String,
+	pub spec_version: u32,
+}
+
+/// Response of `state_getReadProof`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReadProof {
+	pub at: Hash,
+	pub proof: Vec<Bytes>,
+}
+
+/// Response of `system_health`
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+	pub peers: u64,
+	pub is_syncing: bool,
+	pub should_have_peers: bool,
+}
+
+impl Health {
+	pub fn is_healthy(&self) -> bool {
+		!self.is_syncing && !(self.should_have_peers && self.peers == 0)
+	}
+}
+
+enum Transport {
+	Http(HttpClient),
+	// connected on first use and reconnected once the connection is lost
+	Ws(RwLock<Option<Arc<WsClient>>>),
+}
+
+struct Endpoint {
+	url: String,
+	transport: Transport,
+}
+
+impl std::fmt::Debug for Endpoint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Endpoint").field("url", &self.url).finish()
+	}
+}
+
+impl Endpoint {
+	fn new(url: &str) -> Result<Self> {
+		let transport = if url.starts_with("http://") || url.starts_with("https://") {
+			Transport::Http(HttpClientBuilder::default().build(url)?)
+		} else if url.starts_with("ws://") || url.starts_with("wss://") {
+			Transport::Ws(RwLock::new(None))
+		} else {
+			return Err(Error::UrlFormatError(format!(
+				"Kilt client connection must start with http, https, ws or wss, got {:}",
+				url
+			)))
+		};
+		Ok(Endpoint { url: url.to_owned(), transport })
+	}
+
+	async fn request<T: DeserializeOwned>(
+		&self,
+		method: &str,
+		params: Option<Vec<JsonValue>>,
+	) -> std::result::Result<T, RpcError> {
+		match &self.transport {
+			Transport::Http(client) => client.request(method, params.map(Into::into)).await,
+			Transport::Ws(client) => {
+				let client = self.ws_client(client).await?;
+				client.request(method, params.map(Into::into)).await
+			},
+		}
+	}
+
+	async fn ws_client(
+		&self,
+		client: &RwLock<Option<Arc<WsClient>>>,
+	) -> std::result::Result<Arc<WsClient>, RpcError> {
+		if let Some(c) = client.read().await.as_ref().filter(|c| c.is_connected()) {
+			return Ok(c.clone())
+		}
+
+		let mut client = client.write().await;
+		// another request may have reconnected in the meantime
+		if let Some(c) = client.as_ref().filter(|c| c.is_connected()) {
+			return Ok(c.clone())
+		}
+		log::info!(target: KILT_LOG_TARGET, "connecting to kilt endpoint {:}", self.url);
+		let c = Arc::new(WsClientBuilder::default().build(&self.url).await?);
+		*client = Some(c.clone());
+		Ok(c)
+	}
+}
+
+/// Rpc client of the kilt network. Requests go to one endpoint at a time, a connection
+/// failure or an unhealthy node switches to the next one.
 #[derive(Clone, Debug)]
 pub struct KiltClient {
-	client: HttpClient,
+	endpoints: Arc<Vec<Endpoint>>,
+	// index of the endpoint in use, shared by all clones
+	current: Arc<AtomicUsize>,
 }
 
 impl KiltClient {
 	pub async fn try_from_url(url: &str) -> Result<Self> {
-		if url.starts_with("http://") || url.starts_with("https://") {
-			let client = HttpClientBuilder::default().build(&url)?;
-			Ok(KiltClient { client })
-		} else {
-			Err(Error::UrlFormatError(
-				"Kilt client connection must start with http or https".to_owned(),
-			))
+		Self::try_from_urls(&[url.to_owned()]).await
+	}
+
+	/// `urls` in order of preference, each one is http(s) or ws(s)
+	pub async fn try_from_urls(urls: &[String]) -> Result<Self> {
+		if urls.is_empty() {
+			return Err(Error::UrlFormatError("no kilt endpoint is given".to_owned()))
+		}
+		let endpoints = urls.iter().map(|url| Endpoint::new(url)).collect::<Result<Vec<_>>>()?;
+		Ok(KiltClient { endpoints: Arc::new(endpoints), current: Default::default() })
+	}
+
+	// send the request to the current endpoint, and fail over to the next one if the
+	// node cannot be reached, so that the caller's retry hits another node
+	async fn request<T: DeserializeOwned>(
+		&self,
+		method: &str,
+		params: Option<Vec<JsonValue>>,
+	) -> std::result::Result<T, RpcError> {
+		let index = self.current.load(Ordering::SeqCst);
+		let res = self.endpoints[index].request(method, params).await;
+		if let Err(RpcError::RequestTimeout | RpcError::Transport(_) | RpcError::RestartNeeded(_)) =
+			&res
+		{
+			self.switch_from(index, (index + 1) % self.endpoints.len());
+		}
+		res
+	}
+
+	// switch to endpoint `to` unless another request has already left `from`
+	fn switch_from(&self, from: usize, to: usize) {
+		if from != to &&
+			self.current
+				.compare_exchange(from, to, Ordering::SeqCst, Ordering::SeqCst)
+				.is_ok()
+		{
+			log::warn!(
+				target: KILT_LOG_TARGET,
+				"switch kilt endpoint from {:} to {:}",
+				self.endpoints[from].url,
+				self.endpoints[to].url
+			);
+		}
+	}
+
+	/// switch to the next endpoint, e.g. when the current node sent a bad proof. Returns
+	/// false if there is no other endpoint
+	pub fn fail_over(&self) -> bool {
+		if self.endpoints.len() < 2 {
+			return false
+		}
+		let index = self.current.load(Ordering::SeqCst);
+		self.switch_from(index, (index + 1) % self.endpoints.len());
+		true
+	}
+
+	/// use the most preferred healthy endpoint, keep the current one if none is healthy
+	pub async fn health_check(&self) {
+		for (index, endpoint) in self.endpoints.iter().enumerate() {
+			match endpoint.request::<Health>("system_health", None).await {
+				Ok(health) if health.is_healthy() => {
+					self.switch_from(self.current.load(Ordering::SeqCst), index);
+					return
+				},
+				res => log::warn!(
+					target: KILT_LOG_TARGET,
+					"kilt endpoint {:} is unhealthy: {:?}",
+					endpoint.url,
+					res
+				),
+			}
 		}
+		log::error!(target: KILT_LOG_TARGET, "no healthy kilt endpoint");
 	}
 
 	// fetch storage
@@ -68,9 +344,91 @@ impl KiltClient {
 		hash: Option<Hash>,
 	) -> std::result::Result<Option<StorageData>, RpcError> {
 		let params = vec![to_json_value(key)?, to_json_value(hash)?];
-		let data = self.client.request("state_getStorage", Some(params.into())).await?;
+		let data = self.request("state_getStorage", Some(params)).await?;
 		Ok(data)
 	}
+
+	// fetch the storage of all `keys` at the same block `hash`
+	pub async fn request_storage_at(
+		&self,
+		keys: &[StorageKey],
+		hash: Hash,
+	) -> std::result::Result<Vec<(StorageKey, Option<StorageData>)>, RpcError> {
+		let params = vec![to_json_value(keys)?, to_json_value(hash)?];
+		let change_sets: Vec<StorageChangeSet<Hash>> =
+			self.request("state_queryStorageAt", Some(params)).await?;
+		Ok(change_sets.into_iter().flat_map(|set| set.changes).collect())
+	}
+
+	// hash of the best block if `number` is none
+	pub async fn block_hash(
+		&self,
+		number: Option<BlockNumber>,
+	) -> std::result::Result<Option<Hash>, RpcError> {
+		let params = vec![to_json_value(number)?];
+		let hash = self.request("chain_getBlockHash", Some(params)).await?;
+		Ok(hash)
+	}
+
+	pub async fn runtime_version(
+		&self,
+		hash: Option<Hash>,
+	) -> std::result::Result<RuntimeVersion, RpcError> {
+		let params = vec![to_json_value(hash)?];
+		let version = self.request("state_getRuntimeVersion", Some(params)).await?;
+		Ok(version)
+	}
+
+	// fetch the scale encoded `RuntimeMetadataPrefixed`
+	pub async fn metadata(&self, hash: Option<Hash>) -> std::result::Result<Bytes, RpcError> {
+		let params = vec![to_json_value(hash)?];
+		let metadata = self.request("state_getMetadata", Some(params)).await?;
+		Ok(metadata)
+	}
+
+	// fetch the merkle proof of the storage under `keys` at block `hash`
+	pub async fn request_read_proof(
+		&self,
+		keys: &[StorageKey],
+		hash: Hash,
+	) -> std::result::Result<ReadProof, RpcError> {
+		let params = vec![to_json_value(keys)?, to_json_value(hash)?];
+		let proof = self.request("state_getReadProof", Some(params)).await?;
+		Ok(proof)
+	}
+
+	pub async fn finalized_head(&self) -> std::result::Result<Hash, RpcError> {
+		let hash = self.request("chain_getFinalizedHead", None).await?;
+		Ok(hash)
+	}
+
+	pub async fn header(&self, hash: Hash) -> std::result::Result<Option<Header>, RpcError> {
+		let params = vec![to_json_value(hash)?];
+		let header = self.request("chain_getHeader", Some(params)).await?;
+		Ok(header)
+	}
+
+	// fetch the scale encoded grandpa finality proof of block `number`
+	pub async fn prove_finality(
+		&self,
+		number: BlockNumber,
+	) -> std::result::Result<Option<Bytes>, RpcError> {
+		let params = vec![to_json_value(number)?];
+		let proof = self.request("grandpa_proveFinality", Some(params)).await?;
+		Ok(proof)
+	}
+
+	// call a runtime api at block `hash`, return the scale encoded result
+	pub async fn state_call(
+		&self,
+		method: &str,
+		data: Bytes,
+		hash: Option<Hash>,
+	) -> std::result::Result<Bytes, RpcError> {
+		let params = vec![to_json_value(method)?, to_json_value(data)?, to_json_value(hash)?];
+		let res = self.request("state_call", Some(params)).await?;
+		Ok(res)
+	}
 }
 
 /// get the storage key of attestations
@@ -78,6 +436,26 @@ pub fn get_attestation_storage_key<Key: Encode>(key: Key) -> StorageKey {
 	get_storage_map_key(key, ATTESTATION_PALLET_PREFIX, ATTESTATION_STORAGE_PREFIX)
 }
 
+/// get the storage key of delegation nodes
+pub fn get_delegation_node_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	get_storage_map_key(key, DELEGATION_PALLET_PREFIX, DELEGATION_NODES_STORAGE_PREFIX)
+}
+
+/// get the storage key of full dids
+pub fn get_did_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	get_storage_map_key(key, DID_PALLET_PREFIX, DID_STORAGE_PREFIX)
+}
+
+/// get the storage key of deleted dids
+pub fn get_did_blacklist_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	get_storage_map_key(key, DID_PALLET_PREFIX, DID_BLACKLIST_STORAGE_PREFIX)
+}
+
+/// get the storage key of the web3name owned by a did
+pub fn get_web3_name_storage_key<Key: Encode>(key: Key) -> StorageKey {
+	get_storage_map_key(key, WEB3_NAMES_PALLET_PREFIX, WEB3_NAMES_STORAGE_PREFIX)
+}
+
 fn get_storage_map_key<Key: Encode>(
 	key: Key,
 	pallet_prefix: &str,
@@ -89,8 +467,7 @@ fn get_storage_map_key<Key: Encode>(
 	StorageKey(bytes)
 }
 
-#[allow(unused)]
-fn get_storage_value_key(pallet_prefix: &str, storage_prefix: &str) -> StorageKey {
+pub fn get_storage_value_key(pallet_prefix: &str, storage_prefix: &str) -> StorageKey {
 	let mut bytes = sp_core::twox_128(pallet_prefix.as_bytes()).to_vec();
 	bytes.extend(&sp_core::twox_128(storage_prefix.as_bytes())[..]);
 	StorageKey(bytes)
@@ -125,6 +502,66 @@ pub enum Error {
 	Serialization(#[from] serde_json::error::Error),
 	#[error("Error decoding storage value: {0}")]
 	StorageValueDecode(#[from] codec::Error),
+	#[error("Invalid storage proof, err: {0}")]
+	InvalidStorageProof(String),
+	#[error("Finality verification failed, err: {0}")]
+	FinalityError(String),
+	#[error("Invalid finality checkpoint: {0}")]
+	InvalidFinalityCheckpoint(String),
+	#[error("Forced grandpa authority change in block #{0}, a new finality checkpoint is needed")]
+	ForcedAuthorityChange(BlockNumber),
+	#[error("Unsupported kilt runtime, err: {0}")]
+	UnsupportedRuntime(String),
+	#[error("Unexpected response from kilt node: {0}")]
+	UnexpectedResponse(String),
+	#[error("Invalid trusted attester policy: {0}")]
+	InvalidAttesterPolicy(String),
+}
+
+impl Error {
+	// a later attempt may succeed once the node, or the subscription endpoint of the
+	// attestation cache, is reachable again
+	pub fn is_retryable(&self) -> bool {
+		matches!(
+			self,
+			Error::KiltClientError(
+				RpcError::RequestTimeout | RpcError::Transport(_) | RpcError::RestartNeeded(_)
+			)
+		)
+	}
+
+	// the node answered, but its answer does not hold up. Another node may answer right,
+	// asking the same one again does not help
+	pub fn is_untrusted(&self) -> bool {
+		matches!(
+			self,
+			Error::StorageValueDecode(_) |
+				Error::InvalidStorageProof(_) |
+				Error::FinalityError(_) |
+				Error::UnexpectedResponse(_)
+		)
+	}
 }
 
 type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn kilt_client_urls_should_work() {
+		let urls = vec!["http://127.0.0.1:9933".to_owned(), "wss://127.0.0.1:9944".to_owned()];
+		assert!(KiltClient::try_from_urls(&urls).await.is_ok());
+		assert!(KiltClient::try_from_urls(&[]).await.is_err());
+		assert!(KiltClient::try_from_url("ftp://127.0.0.1").await.is_err());
+	}
+
+	#[test]
+	fn node_health_should_work() {
+		let health = Health { peers: 0, is_syncing: false, should_have_peers: false };
+		assert!(health.is_healthy());
+		assert!(!Health { should_have_peers: true, ..health.clone() }.is_healthy());
+		assert!(!Health { peers: 3, is_syncing: true, ..health }.is_healthy());
+	}
+}
@@ -19,7 +19,10 @@ pub use web3::{
 };
 pub use yaque::{Receiver as MqReceiver, Sender as MqSender};
 
-use crate::{kilt::Attestation, moonbeam::Params};
+use crate::{
+	kilt::{Attestation, AttesterIdentity, RejectReason},
+	moonbeam::Params,
+};
 pub use config::{ChannelFiles, Config, ConfigInstance};
 pub use error::Error;
 pub use ipfs::{IpfsClient, IpfsConfig};
@@ -29,10 +32,13 @@ pub use moonbeam::{MoonbeamClient, MoonbeamConfig};
 pub mod config;
 pub mod error;
 pub mod ipfs;
+pub mod keystore;
 pub mod kilt;
 // #[cfg(feature = "monitor")]
 pub mod monitor;
 pub mod moonbeam;
+pub mod registry;
+pub mod signer;
 pub mod verify;
 
 pub const MESSAGE_PARSE_LOG_TARGET: &str = "Message Parse";
@@ -155,6 +161,16 @@ pub struct VerifyResult {
 	pub is_passed: bool,
 	// exp_result in ProofEvent
 	pub calc_output: Vec<u128>,
+	// delegation nodes from the attester up to the hierarchy root,
+	// empty if the attestation is not delegated
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub delegation_chain: Vec<Bytes32>,
+	// set if the kilt component rejected the credential
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reject_reason: Option<RejectReason>,
+	// the did of the attester, set if it has a full did
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub attester_identity: Option<AttesterIdentity>,
 }
 
 impl VerifyResult {
@@ -169,18 +185,36 @@ impl VerifyResult {
 			attester: p.attester,
 			is_passed: passed,
 			calc_output: p.expect_result,
+			delegation_chain: vec![],
+			reject_reason: None,
+			attester_identity: None,
 		}
 	}
 
-	// if the credential is revoked by attester, do nothing and return error
-	pub fn update_from_attestation(&mut self, attest: Attestation) -> std::result::Result<(), ()> {
-		if !attest.revoked {
-			self.c_type = Bytes32::from(attest.ctype_hash);
-			self.attester = Bytes32::from(attest.attester);
-			Ok(())
-		} else {
-			Err(())
+	// the attestation must not be revoked, and must be of the ctype and
+	// from the attester claimed in the `AddProof` event
+	pub fn match_attestation(&self, attest: &Attestation) -> std::result::Result<(), RejectReason> {
+		if attest.revoked {
+			return Err(RejectReason::Revoked)
+		}
+
+		let attested = Bytes32::from(attest.ctype_hash);
+		if attested != self.c_type {
+			return Err(RejectReason::CTypeMismatch { claimed: self.c_type, attested })
 		}
+
+		let attested = Bytes32::from(attest.attester.clone());
+		if attested != self.attester {
+			return Err(RejectReason::AttesterMismatch { claimed: self.attester, attested })
+		}
+
+		Ok(())
+	}
+
+	// the credential will be submitted as not passed
+	pub fn reject(&mut self, reason: RejectReason) {
+		self.is_passed = false;
+		self.reject_reason = Some(reason);
 	}
 
 	// todo: make params a type
@@ -270,6 +304,32 @@ mod tests {
 		assert_eq!(std::str::from_utf8(&event_str).unwrap(), json_str);
 	}
 
+	#[test]
+	fn match_attestation_should_detect_mismatch() {
+		use crate::kilt::{Attestation, Deposit, RejectReason};
+		use sp_runtime::AccountId32;
+
+		let attester = AccountId32::new([1u8; 32]);
+		let mut attestation = Attestation {
+			ctype_hash: [2u8; 32].into(),
+			attester: attester.clone(),
+			authorization_id: None,
+			revoked: false,
+			deposit: Deposit { owner: attester, amount: 0 },
+		};
+		let v_res = VerifyResult { c_type: [2u8; 32], attester: [1u8; 32], ..Default::default() };
+		assert_eq!(v_res.match_attestation(&attestation), Ok(()));
+
+		attestation.ctype_hash = [3u8; 32].into();
+		assert_eq!(
+			v_res.match_attestation(&attestation),
+			Err(RejectReason::CTypeMismatch { claimed: [2u8; 32], attested: [3u8; 32] })
+		);
+
+		attestation.revoked = true;
+		assert_eq!(v_res.match_attestation(&attestation), Err(RejectReason::Revoked));
+	}
+
 	#[test]
 	fn bytes32_segament_parse_should_correct() {
 		// 6b696c744163636f756e74000000000000000000000000000000000000000000
@@ -43,6 +43,16 @@ impl MonitorMetrics {
 		Self { target, block_number, error_msg, keeper_name }
 	}
 
+	// for events worth an alert which are not errors of the keeper itself
+	pub fn new_with_msg(
+		target: String,
+		block_number: Option<U64>,
+		msg: String,
+		keeper_name: String,
+	) -> Self {
+		Self { target, block_number, error_msg: msg, keeper_name }
+	}
+
 	pub fn new_with_target_and_error(
 		target: String,
 		error_with_number: &(Option<U64>, super::Error),
@@ -1,17 +1,23 @@
 use web3::{
 	self as web3,
-	api::Eth,
+	api::{Accounts, Eth},
 	contract::{
 		tokens::{Detokenize, Tokenize},
 		Contract, Error as Web3ContractErr,
 	},
 	ethabi,
 	transports::Http,
+	types::{TransactionParameters, U256},
 	Transport,
 };
 
 pub use super::*;
 use super::{Deserialize, Serialize};
+use crate::{
+	keystore::KeystoreConfig,
+	registry::RegistryConfig,
+	signer::{KeyConfig, RemoteSignerConfig},
+};
 
 pub const MOONBEAM_SCAN_SPAN: usize = 10;
 // TODO: move it to config file
@@ -21,25 +27,417 @@ pub const MOONBEAM_TRANSACTION_CONFIRMATIONS: usize = 1;
 pub const MOONBEAM_SCAN_LOG_TARGET: &str = "MoonbeamScan";
 pub const MOONBEAM_SUBMIT_LOG_TARGET: &str = "MoonbeamSubmit";
 pub const MOONBEAM_RESUBMIT_LOG_TARGET: &str = "MoonbeamReSubmit";
+pub const MOONBEAM_BALANCE_LOG_TARGET: &str = "MoonbeamBalance";
+pub const MOONBEAM_AGGREGATOR_LOG_TARGET: &str = "MoonbeamAggregator";
+pub const MOONBEAM_AUDIT_LOG_TARGET: &str = "MoonbeamAudit";
+// aggregator events, a keeper submitted its verdict and a request was finalized
+pub const AGGREGATOR_VERIFYING_EVENT: &str = "Verifying";
+pub const AGGREGATOR_CANONICAL_EVENT: &str = "Canonical";
 // contract function which keeper use to submit verification result
 pub const SUBMIT_VERIFICATION: &str = "submit";
 pub const SUBMIT_STATUS_QUERY: &str = "hasSubmitted";
 pub const IS_FINISHED: &str = "isFinished";
 pub const QUEUE_EXPIRE_DURATION: u8 = 30;
 pub const RESUBMIT_INTERVAL: u64 = 12;
-pub const MAX_RETRY_TIMES: u8 = 10;
+pub const EIP1559_TX_TYPE: u64 = 2;
+// gas of a plain value transfer, used to fill nonce gaps
+pub const SELF_TRANSFER_GAS: u64 = 21_000;
+// selector of solidity `Error(string)`
+const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const GWEI: u64 = 1_000_000_000;
 
 pub type Params = (Address, Bytes32, Bytes32, Bytes32, bool, Bytes32, Vec<u128>);
 
+/// `Verifying` event of the aggregator, emitted for every submission of a keeper
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyingEvent {
+	pub data_owner: Address,
+	pub request_hash: Bytes32,
+	// the keeper who submitted
+	pub worker: Address,
+	pub attester: Bytes32,
+	pub is_passed: bool,
+}
+
+impl Detokenize for VerifyingEvent {
+	fn from_tokens(tokens: Vec<ethabi::Token>) -> std::result::Result<Self, Web3ContractErr> {
+		let (data_owner, request_hash, worker, attester, is_passed) =
+			<(Address, Bytes32, Address, Bytes32, bool)>::from_tokens(tokens)?;
+		Ok(VerifyingEvent { data_owner, request_hash, worker, attester, is_passed })
+	}
+}
+
+/// `Canonical` event of the aggregator, emitted once the verdict of a request is final
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalEvent {
+	pub data_owner: Address,
+	pub request_hash: Bytes32,
+	pub is_passed: bool,
+}
+
+impl Detokenize for CanonicalEvent {
+	fn from_tokens(tokens: Vec<ethabi::Token>) -> std::result::Result<Self, Web3ContractErr> {
+		let (data_owner, request_hash, is_passed) =
+			<(Address, Bytes32, bool)>::from_tokens(tokens)?;
+		Ok(CanonicalEvent { data_owner, request_hash, is_passed })
+	}
+}
+
 #[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
 pub struct MoonbeamConfig {
 	pub url: String,
 	// where users add their proofs and emit `AddProof` event
 	pub read_contract: String,
-	// where keeper submit the verify result
-	pub write_contract: String,
-	pub private_key: String,
+	// where keeper submit the verify result, overrides the aggregator in the registry
+	#[serde(default)]
+	pub write_contract: Option<String>,
+	// how often the contracts are looked up in the registry
+	#[serde(default)]
+	pub registry: RegistryConfig,
+	// plaintext keys, only taken with `--allow-plaintext-key`
+	#[serde(default)]
+	pub private_key: Option<String>,
 	pub private_key_optional: Option<String>,
+	// keystores of the keys, preferred over the plaintext ones
+	#[serde(default)]
+	pub keystore: Option<KeystoreConfig>,
+	#[serde(default)]
+	pub keystore_optional: Option<KeystoreConfig>,
+	// remote signers of the keys, preferred over the keystores
+	#[serde(default)]
+	pub remote_signer: Option<RemoteSignerConfig>,
+	#[serde(default)]
+	pub remote_signer_optional: Option<RemoteSignerConfig>,
+	// more submit keys, submissions are spread over them and the submit key
+	#[serde(default)]
+	pub submit_pool: Vec<KeyConfig>,
+	// how a submission is assigned to a submit key
+	#[serde(default)]
+	pub scheduler: SchedulerPolicy,
+	// when the balances of the signing keys are too low
+	#[serde(default)]
+	pub balance: BalanceConfig,
+	// do not submit credentials rejected by the kilt component, for
+	// aggregators which can not accept such submissions
+	#[serde(default)]
+	pub skip_rejected: bool,
+	// gas limit of submissions
+	#[serde(default)]
+	pub gas: GasConfig,
+	// fees of submissions and how resubmissions bump them
+	#[serde(default)]
+	pub fee: FeeConfig,
+	// blocks on top of the receipt before a submission is done
+	#[serde(default = "default_confirmations")]
+	pub confirmations: u64,
+	// when a resubmission stops replacing a stuck tx and cancels it
+	#[serde(default)]
+	pub stuck: StuckConfig,
+}
+
+fn default_confirmations() -> u64 {
+	MOONBEAM_TRANSACTION_CONFIRMATIONS as u64
+}
+
+/// How the gas limit of a submission is picked from `eth_estimateGas`
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GasConfig {
+	// added on top of the estimation, in percent
+	pub margin_percent: u64,
+	// the limit never exceeds it
+	pub cap: u64,
+	// used if the estimation fails
+	pub default: u64,
+}
+
+impl Default for GasConfig {
+	fn default() -> Self {
+		GasConfig { margin_percent: 20, cap: 3_000_000, default: 1_000_000 }
+	}
+}
+
+impl GasConfig {
+	// the gas limit for an `estimated` amount of gas, or the default one
+	pub fn limit(&self, estimated: Option<U256>) -> U256 {
+		match estimated {
+			Some(gas) =>
+				(gas.saturating_mul((100 + self.margin_percent).into()) / 100).min(self.cap.into()),
+			None => self.default.into(),
+		}
+	}
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FeeConfig {
+	// send type-2 transactions, falls back to legacy ones if the chain has no base fee
+	pub eip1559: bool,
+	// blocks of `eth_feeHistory` to look at
+	pub fee_history_blocks: u64,
+	// reward percentile of `eth_feeHistory` used as the priority fee
+	pub priority_fee_percentile: u8,
+	pub bump: BumpConfig,
+}
+
+impl Default for FeeConfig {
+	fn default() -> Self {
+		FeeConfig {
+			eip1559: true,
+			fee_history_blocks: 10,
+			priority_fee_percentile: 50,
+			bump: Default::default(),
+		}
+	}
+}
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BumpConfig {
+	// raise the fees of the last attempt by so many percent
+	pub percent: u64,
+	// gas price, or max fee per gas, never exceeds it
+	pub cap_gwei: u64,
+	// how many times a tx is checked before it is resubmitted
+	pub max_attempts: u8,
+}
+
+impl Default for BumpConfig {
+	fn default() -> Self {
+		BumpConfig { percent: 10, cap_gwei: 1_000, max_attempts: 10 }
+	}
+}
+
+impl BumpConfig {
+	pub fn cap(&self) -> U256 {
+		U256::from(self.cap_gwei) * U256::from(GWEI)
+	}
+
+	fn bump(&self, fee: U256) -> U256 {
+		fee.saturating_mul((100 + self.percent).into()) / 100
+	}
+}
+
+/// How the submissions are assigned to the submit keys
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulerPolicy {
+	// one key after another
+	RoundRobin,
+	// the key with the fewest txs not mined yet
+	LeastPending,
+	// always the same key for the same request hash
+	Sticky,
+}
+
+impl Default for SchedulerPolicy {
+	fn default() -> Self {
+		SchedulerPolicy::RoundRobin
+	}
+}
+
+/// Balance thresholds of the signing keys, in gwei of the native token
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BalanceConfig {
+	// seconds between two checks
+	pub interval_secs: u64,
+	// a warning is sent below it
+	pub warn_gwei: u64,
+	// an alert is sent below it
+	pub alert_gwei: u64,
+	// the submissions of the key are held below it
+	pub floor_gwei: u64,
+}
+
+impl Default for BalanceConfig {
+	fn default() -> Self {
+		BalanceConfig {
+			interval_secs: 60,
+			warn_gwei: 1_000_000_000,
+			alert_gwei: 200_000_000,
+			floor_gwei: 50_000_000,
+		}
+	}
+}
+
+impl BalanceConfig {
+	pub fn level(&self, balance: U256) -> BalanceLevel {
+		let gwei = U256::from(GWEI);
+		if balance < U256::from(self.floor_gwei) * gwei {
+			BalanceLevel::Depleted
+		} else if balance < U256::from(self.alert_gwei) * gwei {
+			BalanceLevel::Critical
+		} else if balance < U256::from(self.warn_gwei) * gwei {
+			BalanceLevel::Low
+		} else {
+			BalanceLevel::Healthy
+		}
+	}
+}
+
+/// How bad the balance of a key is, from the best to the worst
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub enum BalanceLevel {
+	Healthy,
+	// below `warn_gwei`
+	Low,
+	// below `alert_gwei`
+	Critical,
+	// below `floor_gwei`, nothing is sent with the key
+	Depleted,
+}
+
+/// A tx which holds up its nonce for too long is cancelled by a self transfer at the same
+/// nonce, and the submission goes on with a fresh one
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StuckConfig {
+	// replacements at the same nonce before it is cancelled
+	pub max_bumps: u8,
+	// blocks a nonce may stay pending before it is cancelled
+	pub deadline_blocks: u64,
+}
+
+impl Default for StuckConfig {
+	fn default() -> Self {
+		StuckConfig { max_bumps: 3, deadline_blocks: 50 }
+	}
+}
+
+impl StuckConfig {
+	// a nonce first sent at block `since` and replaced `bumps` times, seen at block `best`
+	pub fn is_stuck(&self, bumps: u8, since: U64, best: U64) -> bool {
+		bumps >= self.max_bumps || best.saturating_sub(since) >= self.deadline_blocks.into()
+	}
+}
+
+/// Fees a transaction is sent with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fees {
+	Legacy { gas_price: U256 },
+	Eip1559 { max_fee_per_gas: U256, max_priority_fee_per_gas: U256 },
+}
+
+impl Fees {
+	pub fn apply(&self, options: &mut Web3Options) {
+		match *self {
+			Fees::Legacy { gas_price } => options.gas_price = Some(gas_price),
+			Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas } => {
+				options.transaction_type = Some(EIP1559_TX_TYPE.into());
+				options.max_fee_per_gas = Some(max_fee_per_gas);
+				options.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+			},
+		}
+	}
+
+	pub fn apply_to_tx(&self, tx: &mut TransactionParameters) {
+		match *self {
+			Fees::Legacy { gas_price } => tx.gas_price = Some(gas_price),
+			Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas } => {
+				tx.transaction_type = Some(EIP1559_TX_TYPE.into());
+				tx.max_fee_per_gas = Some(max_fee_per_gas);
+				tx.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+			},
+		}
+	}
+
+	pub fn capped(self, cap: U256) -> Self {
+		match self {
+			Fees::Legacy { gas_price } => Fees::Legacy { gas_price: gas_price.min(cap) },
+			Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas } => {
+				let max_fee_per_gas = max_fee_per_gas.min(cap);
+				Fees::Eip1559 {
+					max_fee_per_gas,
+					max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee_per_gas),
+				}
+			},
+		}
+	}
+
+	/// fees of the next attempt: the ones of this attempt bumped, but no less than the
+	/// `suggested` ones and no more than the cap
+	pub fn bumped(&self, suggested: Fees, bump: &BumpConfig) -> Self {
+		let bumped = match (*self, suggested) {
+			(Fees::Legacy { gas_price: last }, Fees::Legacy { gas_price }) =>
+				Fees::Legacy { gas_price: bump.bump(last).max(gas_price) },
+			(
+				Fees::Eip1559 {
+					max_fee_per_gas: last_max,
+					max_priority_fee_per_gas: last_priority,
+				},
+				Fees::Eip1559 { max_fee_per_gas, max_priority_fee_per_gas },
+			) => Fees::Eip1559 {
+				max_fee_per_gas: bump.bump(last_max).max(max_fee_per_gas),
+				max_priority_fee_per_gas: bump.bump(last_priority).max(max_priority_fee_per_gas),
+			},
+			// the chain switched the tx type, start over from the suggestion
+			(_, suggested) => suggested,
+		};
+		bumped.capped(bump.cap())
+	}
+}
+
+/// Why the aggregator reverted a submission, judged from its views
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevertKind {
+	// the key has submitted the request already, nothing left to do
+	AlreadySubmitted,
+	// the request is finished by other keepers, nothing left to do
+	AlreadyFinished,
+	// the keeper account is not allowed to submit
+	NotWorker,
+	// the submission itself is wrong and will revert again
+	BadParams,
+	// the views do not explain it, or can not be read
+	Unknown,
+}
+
+impl RevertKind {
+	// only unknown reverts are worth another submission
+	pub fn should_retry(&self) -> bool {
+		*self == RevertKind::Unknown
+	}
+
+	// the keeper or the credential needs a look
+	pub fn should_alert(&self) -> bool {
+		matches!(self, RevertKind::NotWorker | RevertKind::BadParams)
+	}
+}
+
+// decode the message of a `require`/`revert` from the revert data
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+	if data.len() < 4 || data[..4] != ERROR_STRING_SELECTOR {
+		return None
+	}
+	match ethabi::decode(&[ethabi::ParamType::String], &data[4..]).ok()?.pop()? {
+		ethabi::Token::String(reason) => Some(reason),
+		_ => None,
+	}
+}
+
+// the revert reason of a failed `eth_call` or `eth_estimateGas`, from the revert data or,
+// as some nodes only put it there, the error message
+pub fn revert_reason(e: &web3::Error) -> Option<String> {
+	let e = match e {
+		web3::Error::Rpc(e) => e,
+		_ => return None,
+	};
+	let from_data = e
+		.data
+		.as_ref()
+		.and_then(|d| d.as_str())
+		.and_then(|d| hex::decode(d.trim_start_matches("0x")).ok())
+		.and_then(|d| decode_revert_reason(&d));
+	from_data.or_else(|| {
+		let i = e.message.find("revert")?;
+		let reason = e.message[i..]
+			.trim_start_matches("reverted")
+			.trim_start_matches("revert")
+			.trim_start_matches(':')
+			.trim();
+		Some(reason.to_owned()).filter(|r| !r.is_empty())
+	})
 }
 
 #[derive(Clone, Debug)]
@@ -61,6 +459,10 @@ impl MoonbeamClient {
 		self.inner.eth()
 	}
 
+	pub fn accounts(&self) -> Accounts<Http> {
+		self.inner.accounts()
+	}
+
 	pub async fn best_number(&self) -> Result<U64> {
 		let maybe_best = self.eth().block_number().await;
 		maybe_best.map_err(|e| e.into())
@@ -88,6 +490,16 @@ impl MoonbeamClient {
 		Ok(contract)
 	}
 
+	// get the registry which points to the other contracts
+	pub fn registry_contract(&self, address: Address) -> Result<Contract<Http>> {
+		let contract = Contract::from_json(
+			self.inner.eth(),
+			address,
+			include_bytes!("../contracts/Registry.json"),
+		)?;
+		Ok(contract)
+	}
+
 	#[cfg(test)]
 	pub fn events_contract(&self, contract_addr: &str) -> Result<Contract<Http>> {
 		let address = utils::trim_address_str(contract_addr)?;
@@ -161,7 +573,7 @@ pub mod utils {
 			.collect::<_>()
 	}
 
-	pub(super) fn trim_address_str(addr: &str) -> Result<Address> {
+	pub(crate) fn trim_address_str(addr: &str) -> Result<Address> {
 		let addr = if addr.starts_with("0x") { &addr[2..] } else { addr };
 		let hex_res =
 			hex::decode(addr).map_err(|e| Error::InvalidEthereumAddress(format!("{:}", e)))?;
@@ -192,12 +604,96 @@ pub enum Error {
 
 	#[error("Invalid Ethereum Address: {0}")]
 	InvalidEthereumAddress(String),
+
+	#[error("Local Store Io Error, err: {0}")]
+	StoreIoError(#[from] std::io::Error),
+
+	#[error("Local Store Parse Error, err: {0}")]
+	StoreParseError(#[from] serde_json::Error),
+
+	#[error("Nonce Manager Error: {0}")]
+	NonceError(String),
+
+	#[error("Signer Error, err: {0}")]
+	SignerError(#[from] crate::signer::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
+	use super::*;
+
+	#[test]
+	fn balance_level_should_work() {
+		let config =
+			BalanceConfig { interval_secs: 1, warn_gwei: 30, alert_gwei: 20, floor_gwei: 10 };
+		let gwei = U256::from(GWEI);
+		assert_eq!(config.level(gwei * 30), BalanceLevel::Healthy);
+		assert_eq!(config.level(gwei * 30 - 1), BalanceLevel::Low);
+		assert_eq!(config.level(gwei * 20 - 1), BalanceLevel::Critical);
+		assert_eq!(config.level(gwei * 10 - 1), BalanceLevel::Depleted);
+		assert_eq!(config.level(U256::zero()), BalanceLevel::Depleted);
+		assert!(BalanceLevel::Depleted > BalanceLevel::Low);
+	}
+
+	#[test]
+	fn stuck_should_work() {
+		let stuck = StuckConfig { max_bumps: 2, deadline_blocks: 10 };
+		assert!(!stuck.is_stuck(1, 100.into(), 109.into()));
+		assert!(stuck.is_stuck(2, 100.into(), 101.into()));
+		assert!(stuck.is_stuck(0, 100.into(), 110.into()));
+		// the best block may lag behind
+		assert!(!stuck.is_stuck(0, 100.into(), 90.into()));
+	}
+
+	#[test]
+	fn gas_limit_should_work() {
+		let gas = GasConfig { margin_percent: 20, cap: 1_000, default: 500 };
+		assert_eq!(gas.limit(Some(100.into())), 120.into());
+		assert_eq!(gas.limit(Some(900.into())), 1_000.into());
+		assert_eq!(gas.limit(None), 500.into());
+	}
+
+	#[test]
+	fn bump_fees_should_work() {
+		let bump = BumpConfig { percent: 10, cap_gwei: 1, max_attempts: 10 };
+		let last = Fees::Legacy { gas_price: 100.into() };
+		assert_eq!(
+			last.bumped(Fees::Legacy { gas_price: 50.into() }, &bump),
+			Fees::Legacy { gas_price: 110.into() }
+		);
+		assert_eq!(
+			last.bumped(Fees::Legacy { gas_price: 200.into() }, &bump),
+			Fees::Legacy { gas_price: 200.into() }
+		);
+		assert_eq!(
+			last.bumped(Fees::Legacy { gas_price: (2 * GWEI).into() }, &bump),
+			Fees::Legacy { gas_price: GWEI.into() }
+		);
+
+		let last =
+			Fees::Eip1559 { max_fee_per_gas: 100.into(), max_priority_fee_per_gas: 10.into() };
+		assert_eq!(
+			last.bumped(
+				Fees::Eip1559 { max_fee_per_gas: 90.into(), max_priority_fee_per_gas: 20.into() },
+				&bump
+			),
+			Fees::Eip1559 { max_fee_per_gas: 110.into(), max_priority_fee_per_gas: 20.into() }
+		);
+	}
+
+	#[test]
+	fn decode_revert_reason_should_work() {
+		let mut data = ERROR_STRING_SELECTOR.to_vec();
+		data.extend(ethabi::encode(&[ethabi::Token::String(
+			"Verification is already finished".to_owned(),
+		)]));
+		let reason = decode_revert_reason(&data).unwrap();
+		assert_eq!(reason, "Verification is already finished");
+		assert_eq!(decode_revert_reason(&data[..3]), None);
+	}
+
 	#[test]
 	fn test_cargo_env_variables() {
 		let _contract_name = "KiltProofs";
@@ -0,0 +1,149 @@
+use std::{
+	collections::BTreeMap,
+	sync::{Arc, RwLock},
+};
+
+use super::{
+	moonbeam::{Error, Result},
+	Address, Bytes32, Contract, Deserialize, Http, MoonbeamClient, Serialize, Web3Options,
+};
+
+pub const REGISTRY_LOG_TARGET: &str = "Registry";
+// getter of the registry on the proof storage
+pub const REGISTRY_QUERY: &str = "registry";
+pub const REGISTRY_ADDRESS_OF: &str = "addressOf";
+// getters of the registry keys on the proof storage
+pub const CONTRACT_AGGREGATOR: &str = "CONTRACT_AGGREGATOR";
+pub const CONTRACT_REQUEST: &str = "CONTRACT_REQUEST";
+pub const CONTRACT_REWARD: &str = "CONTRACT_REWARD";
+// the contracts resolved from the registry
+pub const REGISTRY_KEYS: [&str; 3] = [CONTRACT_AGGREGATOR, CONTRACT_REQUEST, CONTRACT_REWARD];
+
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RegistryConfig {
+	// seconds between two lookups of the registry
+	pub refresh_secs: u64,
+}
+
+impl Default for RegistryConfig {
+	fn default() -> Self {
+		RegistryConfig { refresh_secs: 600 }
+	}
+}
+
+/// The addresses the registry points to
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct Resolved {
+	pub registry: Address,
+	pub contracts: BTreeMap<&'static str, Address>,
+}
+
+impl Resolved {
+	pub fn aggregator(&self) -> Option<Address> {
+		self.contracts.get(CONTRACT_AGGREGATOR).cloned()
+	}
+
+	// what changed from `last`, as `(name, from, to)`
+	pub fn changes(&self, last: &Resolved) -> Vec<(&'static str, Address, Address)> {
+		let mut changes = vec![];
+		if self.registry != last.registry {
+			changes.push((REGISTRY_QUERY, last.registry, self.registry));
+		}
+		for (name, address) in &self.contracts {
+			let from = last.contracts.get(name).cloned().unwrap_or_default();
+			if from != *address {
+				changes.push((*name, from, *address));
+			}
+		}
+		changes
+	}
+}
+
+// look the contracts up in the registry the proof storage points to
+pub async fn resolve(client: &MoonbeamClient, proof_contract: &Contract<Http>) -> Result<Resolved> {
+	let registry: Address = proof_contract
+		.query(REGISTRY_QUERY, (), None, Web3Options::default(), None)
+		.await?;
+	let registry_contract = client.registry_contract(registry)?;
+
+	let mut contracts = BTreeMap::new();
+	for name in REGISTRY_KEYS {
+		let key: Bytes32 =
+			proof_contract.query(name, (), None, Web3Options::default(), None).await?;
+		let address: Address = registry_contract
+			.query(REGISTRY_ADDRESS_OF, (key,), None, Web3Options::default(), None)
+			.await?;
+		contracts.insert(name, address);
+	}
+	Ok(Resolved { registry, contracts })
+}
+
+// the aggregator to submit to: the configured one overrides the one in the registry
+pub fn pick_aggregator(overridden: Option<&str>, resolved: Option<&Resolved>) -> Result<String> {
+	match (overridden, resolved.and_then(|r| r.aggregator())) {
+		(Some(address), _) => Ok(address.to_owned()),
+		(None, Some(address)) if !address.is_zero() => Ok(format!("{:?}", address)),
+		_ => Err(Error::ClientCreationError(
+			"The aggregator is neither configured nor found in the registry".to_owned(),
+		)),
+	}
+}
+
+/// A contract which is swapped when the registry points elsewhere, shared by all tasks
+#[derive(Clone, Debug)]
+pub struct SharedContract(Arc<RwLock<Contract<Http>>>);
+
+impl SharedContract {
+	pub fn new(contract: Contract<Http>) -> Self {
+		SharedContract(Arc::new(RwLock::new(contract)))
+	}
+
+	pub fn get(&self) -> Contract<Http> {
+		self.0.read().expect("the contract lock is never poisoned; qed").clone()
+	}
+
+	pub fn set(&self, contract: Contract<Http>) {
+		*self.0.write().expect("the contract lock is never poisoned; qed") = contract;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn changes_should_work() {
+		let last = Resolved {
+			registry: Address::repeat_byte(1),
+			contracts: [(CONTRACT_AGGREGATOR, Address::repeat_byte(2))].into_iter().collect(),
+		};
+		assert!(last.changes(&last).is_empty());
+
+		let mut now = last.clone();
+		now.registry = Address::repeat_byte(3);
+		now.contracts.insert(CONTRACT_AGGREGATOR, Address::repeat_byte(4));
+		assert_eq!(
+			now.changes(&last),
+			vec![
+				(REGISTRY_QUERY, Address::repeat_byte(1), Address::repeat_byte(3)),
+				(CONTRACT_AGGREGATOR, Address::repeat_byte(2), Address::repeat_byte(4)),
+			]
+		);
+	}
+
+	#[test]
+	fn config_overrides_registry() {
+		let resolved = Resolved {
+			contracts: [(CONTRACT_AGGREGATOR, Address::repeat_byte(2))].into_iter().collect(),
+			..Default::default()
+		};
+		assert_eq!(pick_aggregator(Some("0x01"), Some(&resolved)).unwrap(), "0x01");
+		assert_eq!(
+			pick_aggregator(None, Some(&resolved)).unwrap(),
+			format!("{:?}", Address::repeat_byte(2))
+		);
+		assert!(pick_aggregator(None, None).is_err());
+		assert!(pick_aggregator(None, Some(&Resolved::default())).is_err());
+	}
+}
@@ -0,0 +1,338 @@
+use async_trait::async_trait;
+use jsonrpsee::{
+	http_client::{HttpClient, HttpClientBuilder},
+	types::{traits::Client, Error as RpcError, JsonValue},
+};
+use secp256k1::SecretKey;
+use serde_json::json;
+use std::{fmt, sync::Arc};
+use web3::{
+	api::{Accounts, Eth},
+	types::{Bytes as RawTx, TransactionParameters, U256},
+};
+
+use super::{
+	keystore, moonbeam::EIP1559_TX_TYPE, Address, BlockNumber, Deserialize, Http, Key,
+	MoonbeamClient, MoonbeamConfig, SecretKeyRef, Serialize, U64,
+};
+
+pub const SIGNER_LOG_TARGET: &str = "Signer";
+const SIGN_TRANSACTION: &str = "eth_signTransaction";
+
+/// Signs the txs of one submission key
+#[async_trait]
+pub trait Signer: Send + Sync + fmt::Debug {
+	fn address(&self) -> Address;
+
+	/// the raw signed `tx`, fields left out are filled like the node would
+	async fn sign_transaction(&self, tx: TransactionParameters) -> Result<RawTx>;
+}
+
+pub type SignerRef = Arc<dyn Signer>;
+
+/// A key held by the keeper itself
+pub struct LocalSigner {
+	key: SecretKey,
+	address: Address,
+	accounts: Accounts<Http>,
+}
+
+impl LocalSigner {
+	pub fn new(key: SecretKey, client: &MoonbeamClient) -> Self {
+		let address = SecretKeyRef::new(&key).address();
+		LocalSigner { key, address, accounts: client.accounts() }
+	}
+}
+
+// never print the key
+impl fmt::Debug for LocalSigner {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("LocalSigner").field("address", &self.address).finish()
+	}
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+	fn address(&self) -> Address {
+		self.address
+	}
+
+	async fn sign_transaction(&self, tx: TransactionParameters) -> Result<RawTx> {
+		let signed = self.accounts.sign_transaction(tx, &self.key).await?;
+		Ok(signed.raw_transaction)
+	}
+}
+
+/// A signer speaking `eth_signTransaction`, e.g. Web3Signer or Clef
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct RemoteSignerConfig {
+	pub url: String,
+	// the account the signer signs for
+	pub address: String,
+}
+
+/// One key of the submit pool, held like the submit key
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize, Serialize)]
+pub struct KeyConfig {
+	#[serde(default)]
+	pub private_key: Option<String>,
+	#[serde(default)]
+	pub keystore: Option<keystore::KeystoreConfig>,
+	#[serde(default)]
+	pub remote_signer: Option<RemoteSignerConfig>,
+}
+
+pub struct RemoteSigner {
+	url: String,
+	address: Address,
+	client: HttpClient,
+	eth: Eth<Http>,
+}
+
+impl RemoteSigner {
+	pub fn new(config: &RemoteSignerConfig, client: &MoonbeamClient) -> Result<Self> {
+		let address = crate::moonbeam::utils::trim_address_str(&config.address)
+			.map_err(|e| Error::InvalidAddress(e.to_string()))?;
+		Ok(RemoteSigner {
+			url: config.url.clone(),
+			address,
+			client: HttpClientBuilder::default().build(&config.url)?,
+			eth: client.eth(),
+		})
+	}
+
+	// the request of `tx`, with the fields it leaves out filled from the node
+	async fn request(&self, tx: TransactionParameters) -> Result<JsonValue> {
+		let nonce = match tx.nonce {
+			Some(nonce) => nonce,
+			None => self.eth.transaction_count(self.address, Some(BlockNumber::Pending)).await?,
+		};
+		let chain_id = match tx.chain_id {
+			Some(chain_id) => U256::from(chain_id),
+			None => self.eth.chain_id().await?,
+		};
+		let mut request = json!({
+			"from": self.address,
+			"to": tx.to,
+			"gas": tx.gas,
+			"value": tx.value,
+			"data": tx.data,
+			"nonce": nonce,
+			"chainId": chain_id,
+		});
+
+		match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+			(Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => {
+				request["type"] = json!(U64::from(EIP1559_TX_TYPE));
+				request["maxFeePerGas"] = json!(max_fee_per_gas);
+				request["maxPriorityFeePerGas"] = json!(max_priority_fee_per_gas);
+			},
+			_ => {
+				let gas_price = match tx.gas_price {
+					Some(gas_price) => gas_price,
+					None => self.eth.gas_price().await?,
+				};
+				request["gasPrice"] = json!(gas_price);
+			},
+		}
+		Ok(request)
+	}
+}
+
+impl fmt::Debug for RemoteSigner {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("RemoteSigner")
+			.field("url", &self.url)
+			.field("address", &self.address)
+			.finish()
+	}
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+	fn address(&self) -> Address {
+		self.address
+	}
+
+	async fn sign_transaction(&self, tx: TransactionParameters) -> Result<RawTx> {
+		let request = self.request(tx).await?;
+		let response: JsonValue =
+			self.client.request(SIGN_TRANSACTION, Some(vec![request].into())).await?;
+
+		// Web3Signer answers the raw tx, Clef wraps it as `{ raw, tx }`
+		let raw = match &response {
+			JsonValue::String(raw) => Some(raw.as_str()),
+			JsonValue::Object(o) => o.get("raw").and_then(|raw| raw.as_str()),
+			_ => None,
+		}
+		.ok_or_else(|| Error::UnexpectedResponse(response.to_string()))?;
+		let raw = hex::decode(raw.trim_start_matches("0x"))
+			.map_err(|e| Error::UnexpectedResponse(e.to_string()))?;
+		Ok(raw.into())
+	}
+}
+
+/// The signer of the submit key and the one of the optional resubmit key. A remote signer is
+/// preferred over a keystore, a keystore over a plaintext key.
+pub fn signers(
+	config: &MoonbeamConfig,
+	client: &MoonbeamClient,
+	allow_plaintext: bool,
+) -> std::result::Result<(SignerRef, Option<SignerRef>), crate::Error> {
+	let signer = load_signer(
+		&config.remote_signer,
+		&config.keystore,
+		&config.private_key,
+		"private_key",
+		client,
+		allow_plaintext,
+	)?
+	.ok_or_else(|| keystore::Error::MissingKey("private_key".to_owned()))?;
+	let signer_optional = load_signer(
+		&config.remote_signer_optional,
+		&config.keystore_optional,
+		&config.private_key_optional,
+		"private_key_optional",
+		client,
+		allow_plaintext,
+	)?;
+	Ok((signer, signer_optional))
+}
+
+/// The signers of the keys in the submit pool
+pub fn pool_signers(
+	config: &MoonbeamConfig,
+	client: &MoonbeamClient,
+	allow_plaintext: bool,
+) -> std::result::Result<Vec<SignerRef>, crate::Error> {
+	let mut signers = Vec::with_capacity(config.submit_pool.len());
+	for (i, key) in config.submit_pool.iter().enumerate() {
+		let name = format!("submit_pool[{}]", i);
+		let signer = load_signer(
+			&key.remote_signer,
+			&key.keystore,
+			&key.private_key,
+			&name,
+			client,
+			allow_plaintext,
+		)?
+		.ok_or(keystore::Error::MissingKey(name))?;
+		signers.push(signer);
+	}
+	Ok(signers)
+}
+
+fn load_signer(
+	remote: &Option<RemoteSignerConfig>,
+	keystore: &Option<keystore::KeystoreConfig>,
+	plaintext: &Option<String>,
+	name: &str,
+	client: &MoonbeamClient,
+	allow_plaintext: bool,
+) -> std::result::Result<Option<SignerRef>, crate::Error> {
+	if let Some(remote) = remote {
+		let signer = RemoteSigner::new(remote, client)?;
+		log::info!(target: SIGNER_LOG_TARGET, "{} signed by {:?}", name, signer);
+		return Ok(Some(Arc::new(signer)))
+	}
+	let key = keystore::signing_key(keystore, plaintext, name, allow_plaintext)?;
+	Ok(key.map(|key| Arc::new(LocalSigner::new(key, client)) as SignerRef))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("Remote Signer Error: {0}")]
+	RpcError(#[from] RpcError),
+
+	#[error("Signer Web3 Error: {0}")]
+	Web3Error(#[from] web3::Error),
+
+	#[error("Invalid Signer Address: {0}")]
+	InvalidAddress(String),
+
+	#[error("Unexpected Signer Response: {0}")]
+	UnexpectedResponse(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::{
+		io::{AsyncReadExt, AsyncWriteExt},
+		net::TcpListener,
+	};
+
+	// a stand-in signer answering one `eth_signTransaction` with `raw`, hands back the request
+	async fn stand_in_signer(raw: &'static str) -> (String, tokio::task::JoinHandle<JsonValue>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let handle = tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			let mut buf = vec![];
+			let body = loop {
+				let mut chunk = [0u8; 1024];
+				let n = stream.read(&mut chunk).await.unwrap();
+				buf.extend_from_slice(&chunk[..n]);
+				let text = String::from_utf8_lossy(&buf).to_string();
+				if let Some(i) = text.find("\r\n\r\n") {
+					let len = text[..i]
+						.lines()
+						.find_map(|l| {
+							l.to_lowercase()
+								.strip_prefix("content-length:")
+								.map(|v| v.trim().to_owned())
+						})
+						.and_then(|v| v.parse::<usize>().ok())
+						.unwrap_or_default();
+					if buf.len() >= i + 4 + len {
+						break buf[i + 4..i + 4 + len].to_vec()
+					}
+				}
+			};
+			let request: JsonValue = serde_json::from_slice(&body).unwrap();
+			let response =
+				json!({ "jsonrpc": "2.0", "id": request["id"], "result": raw }).to_string();
+			let reply = format!(
+				"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+				response.len(),
+				response
+			);
+			stream.write_all(reply.as_bytes()).await.unwrap();
+			request
+		});
+		(url, handle)
+	}
+
+	#[tokio::test]
+	async fn remote_signer_should_work() {
+		let (url, handle) = stand_in_signer("0x02f8").await;
+		let client = MoonbeamClient::new("http://127.0.0.1:1".to_owned()).unwrap();
+		let config = RemoteSignerConfig {
+			url,
+			address: "0x69d09ef8b6B1a2fECD70F147bA302B8278cafF39".to_owned(),
+		};
+		let signer = RemoteSigner::new(&config, &client).unwrap();
+
+		let tx = TransactionParameters {
+			nonce: Some(7.into()),
+			chain_id: Some(1287),
+			max_fee_per_gas: Some(100.into()),
+			max_priority_fee_per_gas: Some(1.into()),
+			..Default::default()
+		};
+		let raw = signer.sign_transaction(tx).await.unwrap();
+		assert_eq!(raw.0, vec![0x02, 0xf8]);
+
+		let request = handle.await.unwrap();
+		assert_eq!(request["method"], SIGN_TRANSACTION);
+		let tx = &request["params"][0];
+		assert_eq!(tx["from"], "0x69d09ef8b6b1a2fecd70f147ba302b8278caff39");
+		assert_eq!(tx["nonce"], "0x7");
+		assert_eq!(tx["chainId"], "0x507");
+		assert_eq!(tx["type"], "0x2");
+		assert_eq!(tx["maxFeePerGas"], "0x64");
+		assert!(tx.get("gasPrice").is_none());
+	}
+}